@@ -0,0 +1,121 @@
+use colored::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::debug;
+use crate::download::sha256_file;
+
+pub const DEFAULT_CACHE_SIZE_LIMIT: u64 = 4 * 1024 * 1024 * 1024;
+
+// Packages are stored by their sha256 so a package that did not change between two client versions
+// is only ever downloaded once. The modification time of an entry is bumped whenever it is used, eviction
+// removes the least recently used entries first
+#[derive(Clone)]
+pub struct PackageCache {
+    directory: PathBuf,
+    size_limit: u64,
+}
+
+impl PackageCache {
+    pub fn new( directory: PathBuf, size_limit: u64 ) -> PackageCache {
+        PackageCache { directory, size_limit }
+    }
+
+    fn entry_path( &self, sha256: &str ) -> PathBuf {
+        self.directory.join(sha256.to_ascii_lowercase())
+    }
+
+    // Returns the cached package if we have it and it still matches its hash
+    pub fn get( &self, sha256: &str ) -> Option<PathBuf> {
+        let path = self.entry_path(sha256);
+        if !path.is_file() {
+            return None;
+        }
+        match sha256_file(&path) {
+            Ok(found) if found.eq_ignore_ascii_case(sha256) => {
+                if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(path)
+            },
+            _ => {
+                debug(&format!("Cached package {} is corrupted, removing it", sha256.bright_blue()));
+                let _ = std::fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    // Moves an already verified file into the cache and returns its new location
+    pub fn insert( &self, file: &Path, sha256: &str ) -> Result<PathBuf, std::io::Error> {
+        std::fs::create_dir_all(&self.directory)?;
+        let path = self.entry_path(sha256);
+        if std::fs::rename(file, &path).is_err() {
+            // Different filesystems, fall back to copying
+            std::fs::copy(file, &path)?;
+            std::fs::remove_file(file)?;
+        }
+        Ok(path)
+    }
+
+    // Number of entries and their total size in bytes
+    pub fn usage( &self ) -> (usize, u64) {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(_) => return (0, 0),
+        };
+        entries.flatten()
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .fold((0, 0), |(count, size), metadata| (count + 1, size + metadata.len()))
+    }
+
+    pub fn clear( &self ) -> Result<u64, std::io::Error> {
+        let (_, size) = self.usage();
+        if self.directory.exists() {
+            std::fs::remove_dir_all(&self.directory)?;
+        }
+        Ok(size)
+    }
+
+    // Removes the least recently used entries until the cache fits in its size limit, entries in `keep` are never removed
+    pub fn evict( &self, keep: &HashSet<String> ) -> Result<u64, std::io::Error> {
+        self.evict_leaving_room(keep, 0)
+    }
+
+    // Like evict, but the cache has to fit in its size limit together with `room` bytes used elsewhere, e.g. the
+    // files extracted so far during an install
+    pub fn evict_leaving_room( &self, keep: &HashSet<String>, room: u64 ) -> Result<u64, std::io::Error> {
+        let size_limit = self.size_limit.saturating_sub(room);
+        if !self.directory.exists() {
+            return Ok(0);
+        }
+        let mut entries: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+        for entry in std::fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((entry.path(), metadata.len(), metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)));
+            }
+        }
+        let mut total_size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort_by_key(|(_, _, modified)| *modified);
+
+        let mut freed = 0;
+        for (path, size, _) in entries {
+            if total_size <= size_limit {
+                break;
+            }
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if keep.contains(&name) {
+                continue;
+            }
+            debug(&format!("Evicting {} from the package cache", name.bright_blue()));
+            std::fs::remove_file(&path)?;
+            total_size -= size;
+            freed += size;
+        }
+        Ok(freed)
+    }
+}
@@ -0,0 +1,107 @@
+use clap::{Arg, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+
+use crate::config::{Setting, SETTINGS};
+use crate::launch::SCHEME;
+
+#[derive(Parser)]
+#[command(name = "syntax-bootstrapper", version, about = "Installs, updates and launches SYNTAX")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    #[command(about = "Install the current client version if it is missing")]
+    Install,
+    #[command(about = "Install the latest client version, leaving any rollback")]
+    Update,
+    #[command(about = "Launch the client for a syntax-player:// uri")]
+    Launch { uri: String },
+    #[command(about = "Check the current install and fix whatever is missing or modified")]
+    Repair,
+    #[command(about = "Remove SYNTAX from this machine")]
+    Uninstall {
+        #[arg(long, help = "Leave the package cache so a reinstall does not download everything again")]
+        keep_cache: bool,
+    },
+    #[command(about = "Show what is installed")]
+    Status,
+    #[command(about = "Show or change settings")]
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    #[command(about = "Show or clean up the package cache")]
+    Cache {
+        #[command(subcommand)]
+        action: Option<CacheAction>,
+    },
+    #[command(about = "List installed versions or roll back to one")]
+    Versions {
+        #[command(subcommand)]
+        action: Option<VersionsAction>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    #[command(about = "Show the settings in effect")]
+    Show,
+    #[command(about = "Print the location of the config file")]
+    Path,
+    #[command(about = "Print a single setting")]
+    Get { key: String },
+    #[command(about = "Change a setting in the config file")]
+    Set { key: String, value: String },
+    #[command(about = "Reset a setting in the config file to its default")]
+    Unset { key: String },
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    #[command(about = "Show the size of the package cache")]
+    Show,
+    #[command(about = "Remove packages until the cache fits its size limit")]
+    Prune,
+    #[command(about = "Remove every cached package")]
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum VersionsAction {
+    #[command(about = "List installed versions")]
+    List,
+    #[command(about = "Make an older installed version current again")]
+    Rollback { version: Option<String> },
+}
+
+// Parses the command line. A bare uri, which is how the protocol handler starts us, is the same as launch <uri>.
+// Every setting is also a global flag, the ones given are returned next to the command
+pub fn parse( args: &[String] ) -> (Cli, Vec<(&'static Setting, String)>) {
+    let mut args = args.to_vec();
+    let is_uri = args.get(1).map(|arg| arg.to_ascii_lowercase().starts_with(&format!("{}:", SCHEME))).unwrap_or(false);
+    if is_uri {
+        args.insert(1, "launch".to_string());
+    }
+
+    let command = Cli::command().args(SETTINGS.iter().map(|setting| {
+        Arg::new(setting.key)
+            .long(setting.flag.trim_start_matches('-'))
+            .value_name("VALUE")
+            .global(true)
+            .help(format!("Overrides {} from the config file ( or {} )", setting.key, setting.environment_variable))
+    }));
+    let matches = command.get_matches_from(args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    (cli, setting_flags(&matches))
+}
+
+fn setting_flags( matches: &ArgMatches ) -> Vec<(&'static Setting, String)> {
+    // Global flags end up on the subcommand that was used
+    let mut matches = matches;
+    while let Some((_, subcommand_matches)) = matches.subcommand() {
+        matches = subcommand_matches;
+    }
+    SETTINGS.iter().filter_map(|setting| matches.get_one::<String>(setting.key).map(|value| (setting, value.clone()))).collect()
+}
@@ -0,0 +1,122 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::download::DownloadError;
+use crate::fetch::PackageFetcher;
+use crate::info;
+use crate::install::{self, InstallError};
+use crate::inventory;
+use crate::state::InstallState;
+use crate::manifest::Manifest;
+
+// Years the bootstrapper knows about without help from the server, each one is YYYYclient.zip extracted to ClientYYYY
+const BUILTIN_CLIENT_YEARS: [&str; 3] = ["2014", "2018", "2020"];
+
+// A client for an older year that a launch uri can ask for with clientyear:<year>. The manifest of a version can
+// list these to add years or to change the built in ones, e.g.
+// "clients": [ { "year": "2016", "package": "2016client.zip", "directory": "Client2016", "arguments": ["--legacy"] } ]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClientVariant {
+    pub year: String,
+    pub package: String,
+    // Relative to the version directory
+    pub directory: String,
+    // Used instead of the launch mode's executable, for clients that name theirs differently
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<String>,
+    // Appended after the launch mode's arguments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<String>,
+}
+
+impl ClientVariant {
+    pub fn executable_path( &self, version_directory: &Path, default_executable: &str ) -> PathBuf {
+        version_directory.join(&self.directory).join(self.executable.as_deref().unwrap_or(default_executable))
+    }
+}
+
+pub struct ClientRegistry {
+    variants: Vec<ClientVariant>,
+}
+
+impl ClientRegistry {
+    pub fn builtin() -> ClientRegistry {
+        let variants = BUILTIN_CLIENT_YEARS.iter().map(|year| ClientVariant {
+            year: year.to_string(),
+            package: format!("{}client.zip", year),
+            directory: format!("Client{}", year),
+            executable: None,
+            arguments: Vec::new(),
+        }).collect();
+        ClientRegistry { variants }
+    }
+
+    // Variants from the server replace the built in variant of the same year
+    pub fn with_overrides( mut self, overrides: &[ClientVariant] ) -> ClientRegistry {
+        for variant in overrides {
+            self.variants.retain(|existing| existing.year != variant.year);
+            self.variants.push(variant.clone());
+        }
+        self
+    }
+
+    pub fn find( &self, year: &str ) -> Option<&ClientVariant> {
+        self.variants.iter().find(|variant| variant.year == year)
+    }
+
+    // Year clients are left out of the base install and only installed once a launch asks for them
+    pub fn is_client_package( &self, package_name: &str ) -> bool {
+        self.variants.iter().any(|variant| variant.package == package_name)
+    }
+
+    // Variants whose package the manifest does not list, a launch asking for one of these can not install it
+    pub fn missing_from<'a>( &'a self, manifest: &'a Manifest ) -> impl Iterator<Item = &'a ClientVariant> {
+        self.variants.iter().filter(|variant| !manifest.packages.iter().any(|package| package.name == variant.package))
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientInstallError {
+    NotInManifest { year: String, version: String },
+    Download(DownloadError),
+    Install(InstallError),
+}
+
+impl std::fmt::Display for ClientInstallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientInstallError::NotInManifest { year, version } => write!(f, "version {} has no {} client", version, year),
+            ClientInstallError::Download(e) => write!(f, "{}", e),
+            ClientInstallError::Install(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<DownloadError> for ClientInstallError {
+    fn from(e: DownloadError) -> Self {
+        ClientInstallError::Download(e)
+    }
+}
+
+impl From<InstallError> for ClientInstallError {
+    fn from(e: InstallError) -> Self {
+        ClientInstallError::Install(e)
+    }
+}
+
+// Fetches and extracts the client of a year on its first use
+pub async fn install_client_variant( variant: &ClientVariant, manifest: &Manifest, fetcher: &PackageFetcher<'_>, version_directory: &Path ) -> Result<(), ClientInstallError> {
+    let client_manifest = manifest.subset(|package| package.name == variant.package);
+    let package = client_manifest.packages.first().ok_or_else(|| ClientInstallError::NotInManifest { year: variant.year.clone(), version: manifest.version.clone() })?;
+    info(&format!("Installing the {} client, this only happens the first time it is used", variant.year.bright_blue()));
+    let package_paths = fetcher.fetch(&client_manifest).await?;
+    let target_directory = version_directory.join(&variant.directory);
+    install::install_package(package, &package_paths[0], &target_directory)?;
+    if let Some(mut state) = InstallState::load(version_directory) {
+        let files = inventory::package_files(&package.name, package.format(), &package_paths[0])?;
+        state.record(package, files, &target_directory, version_directory);
+        state.save(version_directory).map_err(InstallError::from)?;
+    }
+    Ok(())
+}
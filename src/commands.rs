@@ -0,0 +1,196 @@
+use colored::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::cache::PackageCache;
+use crate::cli::{CacheAction, ConfigAction, VersionsAction};
+use crate::config::{self, Config, ConfigError};
+use crate::delta::INSTALLED_MANIFEST_FILENAME;
+use crate::manifest::Manifest;
+use crate::packs::PackRecord;
+use crate::versions::{self, Rollback};
+use crate::{info, protocol};
+
+// Commands that only look at or change what is on disk, they never talk to the setup servers
+
+fn format_time( time: SystemTime ) -> String {
+    chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn format_size( bytes: u64 ) -> String {
+    format!("{} MB", bytes / 1024 / 1024)
+}
+
+fn package_cache( config: &Config ) -> PackageCache {
+    PackageCache::new(config.installation_directory().join("Cache"), config.cache_size_limit())
+}
+
+// The version launches use, the rolled back one or else the newest install
+fn current_version( config: &Config ) -> Option<String> {
+    let installation_directory = config.installation_directory();
+    Rollback::load(&installation_directory).map(|rollback| rollback.version)
+        .or_else(|| versions::installed_versions(&installation_directory.join("Versions")).first().map(|installed| installed.version.clone()))
+}
+
+pub fn status( config: &Config ) -> Result<(), String> {
+    let installation_directory = config.installation_directory();
+    info(&format!("Bootstrapper Version: {}", env!("CARGO_PKG_VERSION").bright_blue()));
+    info(&format!("Config File: {}", config::config_path().to_str().unwrap().bright_blue()));
+    info(&format!("Installation Directory: {}", installation_directory.to_str().unwrap().bright_blue()));
+    list_versions(config);
+    if let Some(rollback) = Rollback::load(&installation_directory) {
+        info(&format!("Rolled back to {}, skipping {}", rollback.version.bright_blue(), rollback.skipped.bright_blue()));
+    }
+    if let Some(version) = current_version(config) {
+        if let Some(pack_record) = PackRecord::load(&installation_directory.join("Versions").join(&version)) {
+            info(&format!("Optional Packs: {} installed, {} left out", pack_record.present.join(", ").bright_blue(), pack_record.excluded.join(", ").bright_blue()));
+        }
+    }
+    let (entries, size) = package_cache(config).usage();
+    info(&format!("Package Cache: {} packages, {} of {}", entries, format_size(size).bright_blue(), format_size(config.cache_size_limit())));
+    Ok(())
+}
+
+fn list_versions( config: &Config ) {
+    let installed = versions::installed_versions(&config.installation_directory().join("Versions"));
+    if installed.is_empty() {
+        info("No versions installed");
+        return;
+    }
+    let current = current_version(config);
+    for installed_version in installed {
+        let marker = if current.as_deref() == Some(installed_version.version.as_str()) { " (current)" } else { "" };
+        let details = match &installed_version.state {
+            Some(state) => format!(" by bootstrapper {}, {} packages, {}", state.bootstrapper_version, state.packages.len(), format_size(state.installed_size())),
+            None => String::new(),
+        };
+        info(&format!("{}{} installed {}{}", installed_version.version.bright_blue(), marker.green(), format_time(installed_version.installed_at), details));
+    }
+}
+
+pub fn versions( config: &Config, action: Option<VersionsAction>, bootstrapper_filename: &str ) -> Result<(), String> {
+    match action.unwrap_or(VersionsAction::List) {
+        VersionsAction::List => list_versions(config),
+        VersionsAction::Rollback { version } => {
+            let installation_directory = config.installation_directory();
+            let target = versions::rollback(&installation_directory, &installation_directory.join("Versions"), version.as_deref(), bootstrapper_filename)?;
+            protocol::register(&target.path.join(bootstrapper_filename));
+            info(&format!("Rolled back to {}", target.version.cyan().underline()));
+        }
+    }
+    Ok(())
+}
+
+pub fn cache( config: &Config, action: Option<CacheAction> ) -> Result<(), String> {
+    let package_cache = package_cache(config);
+    match action.unwrap_or(CacheAction::Show) {
+        CacheAction::Show => {
+            let (entries, size) = package_cache.usage();
+            info(&format!("{} packages, {} of {}", entries, format_size(size).bright_blue(), format_size(config.cache_size_limit())));
+        },
+        CacheAction::Prune => {
+            // Packages of installed versions stay, repairs and rollbacks need them
+            let keep = versions::installed_versions(&config.installation_directory().join("Versions")).iter()
+                .filter_map(|installed| Manifest::load(&installed.path.join(INSTALLED_MANIFEST_FILENAME)))
+                .flat_map(|manifest| manifest.packages.into_iter().map(|package| package.sha256.to_ascii_lowercase()))
+                .collect::<HashSet<String>>();
+            let freed = package_cache.evict(&keep).map_err(|e| e.to_string())?;
+            info(&format!("Removed {} from the package cache", format_size(freed).bright_blue()));
+        },
+        CacheAction::Clear => {
+            let freed = package_cache.clear().map_err(|e| e.to_string())?;
+            info(&format!("Removed {} from the package cache", format_size(freed).bright_blue()));
+        }
+    }
+    Ok(())
+}
+
+// Changes only ever go to the config file, environment variables and flags are left out
+pub fn config( config: &Config, action: Option<ConfigAction> ) -> Result<(), String> {
+    match action.unwrap_or(ConfigAction::Show) {
+        ConfigAction::Show => print!("{}", toml::to_string_pretty(config).map_err(|e| e.to_string())?),
+        ConfigAction::Path => println!("{}", config::config_path().to_str().unwrap()),
+        ConfigAction::Get { key } => {
+            config::find_setting(&key).ok_or_else(|| ConfigError::UnknownSetting(key.clone()).to_string())?;
+            println!("{}", config.get(&key).unwrap_or_default());
+        },
+        ConfigAction::Set { key, value } => {
+            let setting = config::find_setting(&key).ok_or_else(|| ConfigError::UnknownSetting(key.clone()).to_string())?;
+            let mut file_config = Config::load_file().map_err(|e| e.to_string())?;
+            setting.apply(&mut file_config, &value).map_err(|e| e.to_string())?;
+            file_config.save().map_err(|e| e.to_string())?;
+            info(&format!("Set {} to {}", key.bright_blue(), file_config.get(&key).unwrap_or_default().bright_blue()));
+        },
+        ConfigAction::Unset { key } => {
+            config::find_setting(&key).ok_or_else(|| ConfigError::UnknownSetting(key.clone()).to_string())?;
+            let mut file_config = Config::load_file().map_err(|e| e.to_string())?;
+            file_config.reset(&key);
+            file_config.save().map_err(|e| e.to_string())?;
+            info(&format!("Reset {} to its default", key.bright_blue()));
+        }
+    }
+    Ok(())
+}
+
+// Reverses the install: the scheme handler and whatever else `protocol::register` set up, then the installation directory.
+// The config file stays, it is the user's and not something we installed
+pub fn uninstall( config: &Config, keep_cache: bool ) -> Result<(), String> {
+    let mut removed = protocol::unregister().map_err(|e| format!("Failed to remove the syntax-player scheme: {}", e))?;
+
+    let installation_directory = config.installation_directory();
+    for installed in versions::installed_versions(&installation_directory.join("Versions")) {
+        match installed.state {
+            Some(state) => info(&format!("Removing {}, {} files installed {}", installed.version.bright_blue(), state.file_count(), format_time(installed.installed_at))),
+            None => info(&format!("Removing {}", installed.version.bright_blue())),
+        }
+    }
+    // The config file can be inside the installation directory when install_directory points somewhere unusual
+    let mut keep = vec![config::config_path()];
+    if keep_cache {
+        keep.push(installation_directory.join("Cache"));
+    }
+    if installation_directory.exists() {
+        if keep.iter().any(|path| path.starts_with(&installation_directory)) {
+            remove_except(&installation_directory, &keep, &mut removed)?;
+        } else {
+            std::fs::remove_dir_all(&installation_directory).map_err(|e| format!("Failed to remove {}: {}", installation_directory.to_str().unwrap(), e))?;
+            removed.push(installation_directory.to_str().unwrap().to_string());
+        }
+    }
+
+    if removed.is_empty() {
+        info("SYNTAX is not installed");
+        return Ok(());
+    }
+    for artifact in &removed {
+        info(&format!("Removed {}", artifact.bright_blue()));
+    }
+    if keep_cache && installation_directory.join("Cache").exists() {
+        info(&format!("Kept the package cache in {}", installation_directory.join("Cache").to_str().unwrap().bright_blue()));
+    }
+    if config::config_path().exists() {
+        info(&format!("Kept the config file {}", config::config_path().to_str().unwrap().bright_blue()));
+    }
+    Ok(())
+}
+
+// Removes everything in `directory` but the paths in `keep` and the directories leading to them
+fn remove_except( directory: &Path, keep: &[PathBuf], removed: &mut Vec<String> ) -> Result<(), String> {
+    let entries = std::fs::read_dir(directory).map_err(|e| e.to_string())?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if keep.contains(&path) {
+            continue;
+        }
+        let is_directory = std::fs::symlink_metadata(&path).map(|metadata| metadata.is_dir()).unwrap_or(false);
+        if is_directory && keep.iter().any(|kept| kept.starts_with(&path)) {
+            remove_except(&path, keep, removed)?;
+            continue;
+        }
+        let result = if is_directory { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+        result.map_err(|e| format!("Failed to remove {}: {}", path.to_str().unwrap(), e))?;
+        removed.push(path.to_str().unwrap().to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::launch::TicketPassing;
+use crate::{cache, download, info, mirrors, packs, versions};
+
+// Settings live in <config dir>/Syntax/config.toml ( ~/.config/Syntax on Linux, ~/Library/Preferences/Syntax on macOS,
+// %APPDATA%\Syntax on Windows ), outside of the installation directory so they survive reinstalls and can move it.
+// Every setting can be overridden for a single run with an environment variable or a command line flag, flags win over
+// environment variables, which win over the file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    // Wine binary used to run the client on Linux and macOS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wine_path: Option<String>,
+    // Setup servers to try in order
+    pub mirrors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_directory: Option<PathBuf>,
+    pub download_concurrency: usize,
+    // Used for every request, e.g. http://proxy.example.com:8080 or socks5://127.0.0.1:1080
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    // Installed versions kept around for rolling back, including the current one
+    pub keep_versions: usize,
+    pub cache_size_limit_mb: u64,
+    // Commands the client is started through, e.g. ["gamemoderun"] or ["mangohud", "--dlsym"]
+    pub launch_wrapper: Vec<String>,
+    // Hosts besides the base url that launch uris may point the client at
+    pub allowed_hosts: Vec<String>,
+    pub ticket_passing: TicketPassing,
+    // Optional packs to leave out, unset keeps whatever the previous install had
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub without_packs: Option<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            wine_path: None,
+            mirrors: mirrors::DEFAULT_MIRRORS.iter().map(|host| host.to_string()).collect(),
+            install_directory: None,
+            download_concurrency: download::DEFAULT_DOWNLOAD_CONCURRENCY,
+            proxy: None,
+            keep_versions: versions::DEFAULT_KEEP_VERSIONS,
+            cache_size_limit_mb: cache::DEFAULT_CACHE_SIZE_LIMIT / 1024 / 1024,
+            launch_wrapper: Vec::new(),
+            allowed_hosts: Vec::new(),
+            ticket_passing: TicketPassing::Argument,
+            without_packs: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    InvalidValue { setting: &'static str, value: String },
+    UnknownSetting(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "IO error: {}", e),
+            ConfigError::Parse(e) => write!(f, "failed to parse {}: {}", config_path().to_str().unwrap(), e),
+            ConfigError::InvalidValue { setting, value } => write!(f, "\"{}\" is not a valid value for {}", value, setting),
+            ConfigError::UnknownSetting(key) => write!(f, "there is no setting called {}", key),
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+// A setting with the names it goes by in the config file, on the command line and in the environment
+pub struct Setting {
+    pub key: &'static str,
+    pub flag: &'static str,
+    pub environment_variable: &'static str,
+    set: fn(&mut Config, &str) -> Option<()>,
+}
+
+fn parse_list( value: &str ) -> Vec<String> {
+    value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+}
+
+fn optional( value: &str ) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+pub const SETTINGS: &[Setting] = &[
+    Setting { key: "wine_path", flag: "--wine-path", environment_variable: "SYNTAX_WINE_PATH", set: |config, value| { config.wine_path = optional(value); Some(()) } },
+    Setting { key: "mirrors", flag: "--mirrors", environment_variable: "SYNTAX_MIRRORS", set: |config, value| { config.mirrors = Some(parse_list(value)).filter(|mirrors| !mirrors.is_empty())?; Some(()) } },
+    Setting { key: "install_directory", flag: "--install-directory", environment_variable: "SYNTAX_INSTALL_DIRECTORY", set: |config, value| { config.install_directory = optional(value).map(PathBuf::from); Some(()) } },
+    Setting { key: "download_concurrency", flag: "--download-concurrency", environment_variable: "SYNTAX_DOWNLOAD_CONCURRENCY", set: |config, value| { config.download_concurrency = value.trim().parse().ok().filter(|value| *value > 0)?; Some(()) } },
+    Setting { key: "proxy", flag: "--proxy", environment_variable: "SYNTAX_PROXY", set: |config, value| { config.proxy = optional(value); Some(()) } },
+    Setting { key: "keep_versions", flag: "--keep-versions", environment_variable: "SYNTAX_KEEP_VERSIONS", set: |config, value| { config.keep_versions = value.trim().parse().ok().filter(|value| *value > 0)?; Some(()) } },
+    Setting { key: "cache_size_limit_mb", flag: "--cache-size-limit-mb", environment_variable: "SYNTAX_CACHE_SIZE_LIMIT_MB", set: |config, value| { config.cache_size_limit_mb = value.trim().parse().ok()?; Some(()) } },
+    Setting { key: "launch_wrapper", flag: "--launch-wrapper", environment_variable: "SYNTAX_LAUNCH_WRAPPER", set: |config, value| { config.launch_wrapper = value.split_whitespace().map(String::from).collect(); Some(()) } },
+    Setting { key: "allowed_hosts", flag: "--allowed-hosts", environment_variable: "SYNTAX_ALLOWED_HOSTS", set: |config, value| { config.allowed_hosts = parse_list(value); Some(()) } },
+    Setting { key: "ticket_passing", flag: "--ticket-passing", environment_variable: "SYNTAX_TICKET_PASSING", set: |config, value| { config.ticket_passing = TicketPassing::from_name(value)?; Some(()) } },
+    Setting { key: "without_packs", flag: "--without", environment_variable: "SYNTAX_WITHOUT_PACKS", set: |config, value| { config.without_packs = Some(packs::parse_pack_list(value)); Some(()) } },
+];
+
+impl Setting {
+    pub fn apply( &self, config: &mut Config, value: &str ) -> Result<(), ConfigError> {
+        (self.set)(config, value).ok_or_else(|| ConfigError::InvalidValue { setting: self.key, value: value.to_string() })
+    }
+}
+
+// The config directory on macOS is ~/Library/Application Support, the same as the data directory the installation
+// lives in, so preferences go where macOS keeps them instead
+pub fn config_path() -> PathBuf {
+    #[cfg(target_os = "macos")]
+    let config_directory = dirs::preference_dir().unwrap();
+    #[cfg(not(target_os = "macos"))]
+    let config_directory = dirs::config_dir().unwrap();
+    config_directory.join("Syntax").join("config.toml")
+}
+
+impl Config {
+    // Just the config file, without any overrides
+    pub fn load_file() -> Result<Config, ConfigError> {
+        match std::fs::read_to_string(config_path()) {
+            Ok(body) => toml::from_str(&body).map_err(ConfigError::Parse),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The config file, then the environment, then the flags given on the command line
+    pub fn load( flags: &[(&Setting, String)] ) -> Result<Config, ConfigError> {
+        let mut config = Config::load_file()?;
+        if config.wine_path.is_none() {
+            config.migrate_wine_path_file()?;
+        }
+        for setting in SETTINGS {
+            if let Ok(value) = std::env::var(setting.environment_variable) {
+                setting.apply(&mut config, &value)?;
+            }
+        }
+        for (setting, value) in flags {
+            setting.apply(&mut config, value)?;
+        }
+        // The flag and the environment variable refuse an empty list, the file can still have mirrors = []
+        if config.mirrors.is_empty() {
+            return Err(ConfigError::InvalidValue { setting: "mirrors", value: "[]".to_string() });
+        }
+        Ok(config)
+    }
+
+    fn to_table( &self ) -> toml::Table {
+        toml::Table::try_from(self).expect("the config is always a table")
+    }
+
+    // A single setting as it would be written to the config file, None when it is unset
+    pub fn get( &self, key: &str ) -> Option<String> {
+        self.to_table().get(key).map(|value| match value {
+            toml::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        })
+    }
+
+    // Puts a setting back to its default
+    pub fn reset( &mut self, key: &str ) {
+        let mut table = self.to_table();
+        match Config::default().to_table().remove(key) {
+            Some(value) => table.insert(key.to_string(), value),
+            None => table.remove(key),
+        };
+        *self = table.try_into().expect("a default value is always valid");
+    }
+
+    pub fn save( &self ) -> Result<(), ConfigError> {
+        let path = config_path();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let body = toml::to_string_pretty(self).map_err(|e| ConfigError::Io(std::io::Error::other(e)))?;
+        std::fs::write(path, body)?;
+        Ok(())
+    }
+
+    // Older versions read the wine path from winepath.txt in the installation directory
+    fn migrate_wine_path_file( &mut self ) -> Result<(), ConfigError> {
+        let wine_path_file = self.installation_directory().join("winepath.txt");
+        let wine_path = match std::fs::read_to_string(&wine_path_file) {
+            Ok(wine_path) => wine_path,
+            Err(_) => return Ok(()),
+        };
+        self.wine_path = optional(&wine_path);
+        self.save()?;
+        std::fs::remove_file(&wine_path_file)?;
+        info(&format!("Moved the wine path from {} to {}", wine_path_file.to_str().unwrap().bright_blue(), config_path().to_str().unwrap().bright_blue()));
+        Ok(())
+    }
+
+    pub fn installation_directory( &self ) -> PathBuf {
+        self.install_directory.clone().unwrap_or_else(|| dirs::data_local_dir().unwrap().join("Syntax"))
+    }
+
+    pub fn wine( &self ) -> &str {
+        self.wine_path.as_deref().unwrap_or("wine64")
+    }
+
+    pub fn cache_size_limit( &self ) -> u64 {
+        self.cache_size_limit_mb * 1024 * 1024
+    }
+}
+
+pub fn find_setting( key: &str ) -> Option<&'static Setting> {
+    SETTINGS.iter().find(|setting| setting.key == key)
+}
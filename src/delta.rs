@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::manifest::Manifest;
+use crate::versions::installed_versions;
+
+// Name of the manifest copy kept in every installed version directory
+pub const INSTALLED_MANIFEST_FILENAME: &str = "manifest.json";
+
+// Patches are made with --long=31 at most, the decoder refuses larger windows unless told otherwise
+const PATCH_WINDOW_LOG_MAX: u32 = 31;
+
+#[derive(Debug, Default)]
+pub struct VersionDiff {
+    pub unchanged: Vec<String>,
+    pub changed: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+// Compares two package sets by package name
+pub fn diff( old: &Manifest, new: &Manifest ) -> VersionDiff {
+    let old_packages = old.packages.iter().map(|package| (package.name.as_str(), package)).collect::<HashMap<&str, _>>();
+    let mut diff = VersionDiff::default();
+    for package in &new.packages {
+        match old_packages.get(package.name.as_str()) {
+            Some(old_package) if old_package.sha256.eq_ignore_ascii_case(&package.sha256) && old_package.destination == package.destination => diff.unchanged.push(package.name.clone()),
+            Some(_) => diff.changed.push(package.name.clone()),
+            None => diff.added.push(package.name.clone()),
+        }
+    }
+    for package in &old.packages {
+        if !new.packages.iter().any(|new_package| new_package.name == package.name) {
+            diff.removed.push(package.name.clone());
+        }
+    }
+    diff
+}
+
+// The manifest of the most recently installed version other than `current_version`, if there is one
+pub fn find_previous_manifest( versions_directory: &Path, current_version: &str ) -> Option<Manifest> {
+    installed_versions(versions_directory).into_iter()
+        .filter(|installed| installed.version != current_version)
+        .find_map(|installed| Manifest::load(&installed.path.join(INSTALLED_MANIFEST_FILENAME)))
+}
+
+// Rebuilds a package from the build it was diffed against and a zstd --patch-from patch
+pub fn apply_patch( base: &Path, patch: &Path, output: &Path ) -> Result<(), std::io::Error> {
+    let base_bytes = std::fs::read(base)?;
+    let patch_file = BufReader::new(std::fs::File::open(patch)?);
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(patch_file, &base_bytes)?;
+    decoder.window_log_max(PATCH_WINDOW_LOG_MAX)?;
+    let mut output_file = std::fs::File::create(output)?;
+    std::io::copy(&mut decoder, &mut output_file)?;
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use colored::*;
+use futures_util::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Client, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::PublishedFile;
+use crate::mirrors::Mirrors;
+use crate::retry::{parse_retry_after, with_retry, RetryPolicy};
+use crate::{debug, error, info};
+
+#[cfg(target_os = "windows")]
+use std::os::windows::prelude::FileExt;
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::fs::FileExt;
+
+// How many times a package is downloaded again after failing verification before we give up
+const MAX_VERIFY_ATTEMPTS: u32 = 3;
+
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 4;
+
+pub struct DownloadRequest {
+    // Relative to the mirror, e.g. version-abcdef-SyntaxApp.zip
+    pub path: String,
+    pub size: Option<u64>,
+    pub sha256: String,
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Http(reqwest::Error),
+    Status { url: String, status: StatusCode, retry_after: Option<std::time::Duration> },
+    Io(std::io::Error),
+    HashMismatch { name: String, expected: String, found: String },
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Http(e) => write!(f, "HTTP error: {}", e),
+            DownloadError::Status { url, status, .. } => write!(f, "{} returned {}", url, status),
+            DownloadError::Io(e) => write!(f, "IO error: {}", e),
+            DownloadError::HashMismatch { name, expected, found } => write!(f, "{} failed verification, expected sha256 {} but got {}", name, expected, found),
+        }
+    }
+}
+
+impl DownloadError {
+    // Connection problems, dropped transfers, 429 and 5xx are worth another try, anything else will fail the same way again
+    pub fn is_retryable( &self ) -> bool {
+        match self {
+            DownloadError::Http(e) => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+            DownloadError::Status { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+            DownloadError::Io(e) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+            DownloadError::HashMismatch { .. } => false,
+        }
+    }
+
+    // Errors caused by this machine rather than the server, another mirror would not help
+    pub fn is_local( &self ) -> bool {
+        matches!(self, DownloadError::Io(e) if e.kind() != std::io::ErrorKind::UnexpectedEof)
+    }
+
+    pub fn retry_after( &self ) -> Option<std::time::Duration> {
+        match self {
+            DownloadError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+// Turns 4xx / 5xx responses into an error, keeping Retry-After around for the retry policy
+fn check_status( url: &str, response: Response ) -> Result<Response, DownloadError> {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        return Err(DownloadError::Status { url: url.to_string(), status, retry_after: parse_retry_after(response.headers()) });
+    }
+    Ok(response)
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        DownloadError::Http(e)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+pub async fn http_get( client: &Client, url: &str, retry_policy: &RetryPolicy ) -> Result<String, DownloadError> {
+    with_retry(retry_policy, url, None, || async {
+        debug(&format!("{} {}", "GET".green(), url.bright_blue()));
+        let response = client.get(url).send().await.map_err(DownloadError::from).and_then(|response| check_status(url, response));
+        if let Err(e) = response {
+            debug(&format!("Failed to fetch {}", url.bright_blue()));
+            return Err(e);
+        }
+        let response_body = response.unwrap().text().await?;
+        Ok(response_body)
+    }).await
+}
+
+// Sits next to a partially downloaded file and holds the ETag / Last-Modified value the download started
+// with, so a resumed download can ask the server for the rest of the same file with If-Range
+fn resume_info_path( path: &Path ) -> PathBuf {
+    let mut file_name = path.file_name().unwrap().to_os_string();
+    file_name.push(".resume");
+    path.with_file_name(file_name)
+}
+
+pub fn is_partial_download( path: &Path ) -> bool {
+    resume_info_path(path).exists()
+}
+
+// Returns the first byte offset of a "Content-Range: bytes 100-999/1000" header
+fn content_range_start( response: &Response ) -> Option<u64> {
+    let content_range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = content_range.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+fn response_validator( response: &Response ) -> Option<String> {
+    // Weak ETags are not allowed in If-Range, fall back to Last-Modified for those
+    let etag = response.headers().get(ETAG).and_then(|value| value.to_str().ok()).filter(|etag| !etag.starts_with("W/"));
+    let last_modified = response.headers().get(LAST_MODIFIED).and_then(|value| value.to_str().ok());
+    etag.or(last_modified).map(|validator| validator.to_string())
+}
+
+pub async fn download_file( client: &Client, url: &str, path: &Path, expected_size: Option<u64>, progress: &MultiProgress ) -> Result<(), DownloadError> {
+    let resume_path = resume_info_path(path);
+    let mut offset = std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    if offset > 0 && Some(offset) == expected_size {
+        debug(&format!("{} is already downloaded", url.bright_blue()));
+        let _ = std::fs::remove_file(&resume_path);
+        return Ok(());
+    }
+
+    debug(&format!("{} {}", "GET".green(), url.bright_blue()));
+    let mut request = client.get(url);
+    if offset > 0 {
+        debug(&format!("Requesting {} from byte {}", url.bright_blue(), offset));
+        request = request.header(RANGE, format!("bytes={}-", offset));
+        if let Ok(validator) = std::fs::read_to_string(&resume_path) {
+            request = request.header(IF_RANGE, validator.trim());
+        }
+    }
+    let mut response = request.send().await?;
+    let resuming = offset > 0 && response.status() == StatusCode::PARTIAL_CONTENT && content_range_start(&response) == Some(offset);
+    if offset > 0 && !resuming {
+        // A 200 means the server ignored the range (or the file changed) and is sending the whole file,
+        // anything else (416, a range we did not ask for) gets a fresh request without a range
+        if response.status() != StatusCode::OK {
+            debug(&format!("{} {} (restarting)", "GET".green(), url.bright_blue()));
+            response = client.get(url).send().await?;
+        }
+        progress.suspend(|| info(&format!("Could not resume {}, downloading from the start", url.bright_blue())));
+        offset = 0;
+    }
+    let response = check_status(url, response)?;
+    let content_length = response.content_length().map(|length| length + offset).or(expected_size).unwrap_or(0);
+    debug(&format!("Content Length: {}", content_length));
+    if resuming {
+        progress.suspend(|| info(&format!("Resuming download of {} from {} bytes", url.bright_blue(), offset)));
+    }
+
+    match response_validator(&response) {
+        Some(validator) => std::fs::write(&resume_path, validator)?,
+        None => {
+            let _ = std::fs::remove_file(&resume_path);
+        }
+    }
+
+    let time = chrono::Local::now().format("%H:%M:%S").to_string();
+    let pg_bar_str = "                {spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
+    let progress_bar = progress.add(ProgressBar::new(content_length));
+    let pg_header_str = format!(
+        "[{}] [{}] Downloading {}",
+        time.bold().blue(),
+        "INFO".bold().green(),
+        &url.bright_blue()
+    );
+    let progress_style = ProgressStyle::default_bar()
+        .template(format!("{}\n{}", pg_header_str, pg_bar_str).as_str())
+        .unwrap().progress_chars("#>-");
+    progress_bar.set_style(progress_style);
+    progress_bar.set_message("Downloading File");
+    progress_bar.set_position(offset);
+
+    let file = if resuming {
+        std::fs::OpenOptions::new().write(true).open(path)?
+    } else {
+        std::fs::File::create(path)?
+    };
+    let mut downloaded: u64 = offset;
+    let mut stream = response.bytes_stream();
+
+    // On error the partial file and its resume info are left in place for the next attempt
+    while let Some(item) = stream.next().await {
+        let chunk = item?;
+        #[cfg(target_os = "windows")]
+        {
+            file.seek_write(chunk.as_ref(), downloaded)?;
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            file.write_at(chunk.as_ref(), downloaded)?;
+        }
+        downloaded += chunk.len() as u64;
+        progress_bar.set_position(std::cmp::min(downloaded, content_length));
+    }
+    if content_length > 0 && downloaded < content_length {
+        progress_bar.finish_and_clear();
+        progress.remove(&progress_bar);
+        return Err(DownloadError::Io(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("connection closed after {} of {} bytes", downloaded, content_length))));
+    }
+    progress_bar.finish_and_clear();
+    progress.remove(&progress_bar);
+    let _ = std::fs::remove_file(&resume_path);
+    progress.suspend(|| info(format!("Finished downloading {}", url.green()).as_str()));
+    Ok(())
+}
+
+// Retries the download according to the retry policy, picking up where the previous attempt stopped
+pub async fn download_file_with_retry( client: &Client, url: &str, path: &Path, expected_size: Option<u64>, retry_policy: &RetryPolicy ) -> Result<(), DownloadError> {
+    let progress = MultiProgress::new();
+    with_retry(retry_policy, url, Some(&progress), || download_file(client, url, path, expected_size, &progress)).await
+}
+
+// Downloads a file that is not a package ( like the bootstrapper ) from the first mirror that has it, checked against
+// the digest the manifest publishes for it, see verify_published
+pub async fn download_file_from_mirrors( client: &Client, mirrors: &Mirrors, path: &str, local_path: &Path, published: Option<&PublishedFile>, retry_policy: &RetryPolicy ) -> Result<(), DownloadError> {
+    mirrors.run(path, None, |url| async move {
+        download_file_with_retry(client, &url, local_path, published.map(|file| file.size), retry_policy).await?;
+        verify_published(local_path, path, published)
+    }).await
+}
+
+// Files that are not packages are only checked when the manifest lists them, setup servers from before "files"
+// publish no digest for them. Those are still used, but never without saying so
+pub fn verify_published( path: &Path, name: &str, published: Option<&PublishedFile> ) -> Result<(), DownloadError> {
+    match published {
+        Some(published) => verify_sha256(path, name, &published.sha256).inspect_err(|_| {
+            let _ = std::fs::remove_file(path);
+        }),
+        None => {
+            error(&format!("The manifest publishes no sha256 for {}, it could not be verified", name.bright_blue()));
+            Ok(())
+        }
+    }
+}
+
+// Downloads a file from the first mirror that has it and checks it against the sha256 digest from the manifest.
+// A file that does not match is deleted and fetched again ( from the next mirror if there is one ) so a truncated
+// or tampered package never reaches the extractor. Partial downloads are named after the mirror relative path so
+// they can be resumed from any mirror
+pub async fn download_verified( client: &Client, mirrors: &Mirrors, request: &DownloadRequest, path_prefix: &Path, retry_policy: &RetryPolicy, progress: &MultiProgress ) -> Result<PathBuf, DownloadError> {
+    let path = path_prefix.join(generate_md5(&request.path).await);
+    let mut attempt = 1;
+    loop {
+        let result = mirrors.run(&request.path, Some(progress), |url| {
+            let path = &path;
+            async move {
+                with_retry(retry_policy, &url, Some(progress), || download_file(client, &url, path, request.size, progress)).await?;
+                verify_sha256(path, &url, &request.sha256).inspect_err(|_| {
+                    let _ = std::fs::remove_file(path);
+                })
+            }
+        }).await;
+        match result {
+            Ok(()) => {
+                debug(&format!("Verified {}", request.path.bright_blue()));
+                return Ok(path);
+            },
+            Err(e @ DownloadError::HashMismatch { .. }) if attempt < MAX_VERIFY_ATTEMPTS => {
+                progress.suspend(|| error(&format!("{}, downloading again (attempt {}/{})", e, attempt + 1, MAX_VERIFY_ATTEMPTS)));
+                attempt += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Downloads and verifies every request with up to `concurrency` downloads in flight at once. Each file is handed to
+// `on_ready` together with the index of its request as soon as it is verified, in whatever order they finish.
+// The first failure, of a download or of `on_ready`, cancels the downloads still running
+pub async fn download_each<E, F>( client: &Client, mirrors: &Mirrors, requests: &[DownloadRequest], path_prefix: &Path, concurrency: usize, retry_policy: &RetryPolicy, mut on_ready: F ) -> Result<(), E>
+where
+    E: From<DownloadError>,
+    F: FnMut(usize, PathBuf) -> Result<(), E>,
+{
+    if requests.is_empty() {
+        return Ok(());
+    }
+    let progress = MultiProgress::new();
+    let overall_bar = progress.add(ProgressBar::new(requests.len() as u64));
+    overall_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("                {spinner:.green} [{bar:40.green/white}] {pos}/{len} packages ({elapsed})")
+            .unwrap().progress_chars("#>-")
+    );
+    overall_bar.enable_steady_tick(std::time::Duration::from_millis(250));
+
+    let mut downloads = futures::stream::iter(requests.iter().enumerate())
+        .map(|(index, request)| {
+            let progress = &progress;
+            let overall_bar = &overall_bar;
+            async move {
+                let path = download_verified(client, mirrors, request, path_prefix, retry_policy, progress).await?;
+                overall_bar.inc(1);
+                Ok::<(usize, PathBuf), DownloadError>((index, path))
+            }
+        })
+        .buffer_unordered(concurrency.max(1));
+    let mut result = Ok(());
+    while let Some(download) = downloads.next().await {
+        result = download.map_err(E::from).and_then(|(index, path)| on_ready(index, path));
+        if result.is_err() {
+            break;
+        }
+    }
+    overall_bar.finish_and_clear();
+    result
+}
+
+pub fn sha256_file( path: &Path ) -> Result<String, std::io::Error> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn verify_sha256( path: &Path, name: &str, expected_sha256: &str ) -> Result<(), DownloadError> {
+    let found = sha256_file(path)?;
+    if !found.eq_ignore_ascii_case(expected_sha256) {
+        return Err(DownloadError::HashMismatch { name: name.to_string(), expected: expected_sha256.to_string(), found });
+    }
+    Ok(())
+}
+
+pub async fn generate_md5( input : &str ) -> String {
+    let hashed_input = md5::compute(input.as_bytes());
+    format!("{:x}", hashed_input)
+}
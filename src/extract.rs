@@ -0,0 +1,482 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+// Packages come from the setup server and its mirrors, an archive is only trusted as far as these checks go:
+// every entry has to stay inside the target directory, nothing is written through a symlink, symlinks may only point
+// inside it and nothing may decompress to more than the limits allow. They apply the same to every archive format
+
+// Entries smaller than this are never held to the compression ratio, tiny files compress unpredictably
+const RATIO_CHECK_MIN_SIZE: u64 = 1024 * 1024;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+// Zip stores the target of a symlink as its contents, which is read before any of the limits apply
+const MAX_SYMLINK_TARGET_LENGTH: u64 = 4096;
+
+// Chosen by the manifest, or else by the extension of the package name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.zst")]
+    TarZstd,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+}
+
+impl ArchiveFormat {
+    // Anything that is not recognizably a tar archive is a zip, like every package used to be
+    pub fn from_name( name: &str ) -> ArchiveFormat {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            ArchiveFormat::TarZstd
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            ArchiveFormat::TarXz
+        } else {
+            ArchiveFormat::Zip
+        }
+    }
+}
+
+pub enum EntryKind {
+    Directory,
+    File,
+    Symlink(String),
+}
+
+pub struct ArchiveEntry<'a> {
+    // As stored in the archive, see entry_path
+    pub name: String,
+    pub kind: EntryKind,
+    // Unix permission bits, when the archive has them
+    pub mode: Option<u32>,
+    // Only zip compresses each entry on its own
+    pub compressed_size: Option<u64>,
+    pub reader: &'a mut dyn Read,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractLimits {
+    pub max_total_size: u64,
+    pub max_entries: usize,
+    // Uncompressed size divided by compressed size, per entry
+    pub max_compression_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_total_size: 16 * 1024 * 1024 * 1024,
+            max_entries: 200_000,
+            max_compression_ratio: 1000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExtractError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    UnsafePath { entry: String },
+    SymlinkEscapes { entry: String, target: String },
+    ThroughSymlink { entry: String },
+    InvalidSymlink { entry: String },
+    UnsupportedEntry { entry: String },
+    #[cfg(not(unix))]
+    UnsupportedSymlink { entry: String },
+    TooManyEntries { limit: usize },
+    TooLarge { entry: String, limit: u64 },
+    CompressionRatio { entry: String, limit: u64 },
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Io(e) => write!(f, "IO error: {}", e),
+            ExtractError::Zip(e) => write!(f, "invalid archive: {}", e),
+            ExtractError::UnsafePath { entry } => write!(f, "entry \"{}\" points outside of the target directory", entry),
+            ExtractError::SymlinkEscapes { entry, target } => write!(f, "symlink \"{}\" points at \"{}\" outside of the target directory", entry, target),
+            ExtractError::ThroughSymlink { entry } => write!(f, "entry \"{}\" would be written through a symlink", entry),
+            ExtractError::InvalidSymlink { entry } => write!(f, "symlink \"{}\" has a target that is too long or not UTF-8", entry),
+            ExtractError::UnsupportedEntry { entry } => write!(f, "entry \"{}\" is neither a file, a directory nor a symlink", entry),
+            #[cfg(not(unix))]
+            ExtractError::UnsupportedSymlink { entry } => write!(f, "entry \"{}\" is a symlink, which is not supported on this platform", entry),
+            ExtractError::TooManyEntries { limit } => write!(f, "archive has more than {} entries", limit),
+            ExtractError::TooLarge { entry, limit } => write!(f, "entry \"{}\" takes the archive past {} bytes uncompressed", entry, limit),
+            ExtractError::CompressionRatio { entry, limit } => write!(f, "entry \"{}\" decompresses to more than {} times its compressed size", entry, limit),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExtractError {
+    fn from(e: std::io::Error) -> Self {
+        ExtractError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ExtractError {
+    fn from(e: zip::result::ZipError) -> Self {
+        ExtractError::Zip(e)
+    }
+}
+
+// Where an entry goes relative to the target directory, None for entries that name the directory itself.
+// Absolute paths, drive letters and .. are refused instead of being cleaned up
+pub fn entry_path( name: &str ) -> Result<Option<PathBuf>, ExtractError> {
+    let mut path = PathBuf::new();
+    for component in Path::new(&name.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {},
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(ExtractError::UnsafePath { entry: name.to_string() }),
+        }
+    }
+    Ok(if path.as_os_str().is_empty() { None } else { Some(path) })
+}
+
+// Resolves a symlink target against the directory of the link without touching the filesystem. A .. after a name
+// is refused since that name could be another symlink by the time the link is followed, only the leading ones are
+// resolved and those walk up real directories
+fn symlink_stays_inside( link_path: &Path, target: &str ) -> bool {
+    let mut resolved = link_path.parent().map(|parent| parent.components().count()).unwrap_or(0);
+    let mut descended = false;
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => descended = true,
+            Component::CurDir => {},
+            Component::ParentDir if resolved > 0 && !descended => resolved -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+// Whether anything between the target directory and `relative_path` is a symlink already on disk, left by this
+// archive, an earlier package or an earlier install. `including_itself` also checks the path, which File::create
+// and create_dir_all would follow
+pub fn through_symlink( target_directory: &Path, relative_path: &Path, including_itself: bool ) -> bool {
+    relative_path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .skip(if including_itself { 0 } else { 1 })
+        .any(|ancestor| target_directory.join(ancestor).symlink_metadata().map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false))
+}
+
+// Copies at most `limit` bytes, returns None when the reader had more than that
+pub fn copy_limited<R: Read + ?Sized, W: Write>( reader: &mut R, writer: &mut W, limit: u64 ) -> Result<Option<u64>, std::io::Error> {
+    let copied = std::io::copy(&mut reader.take(limit.saturating_add(1)), writer)?;
+    Ok(if copied > limit { None } else { Some(copied) })
+}
+
+// Calls `visit` with every entry of an archive in the order they are stored, whatever the format
+pub fn for_each_entry<F>( archive_path: &Path, format: ArchiveFormat, mut visit: F ) -> Result<(), ExtractError>
+where
+    F: FnMut(ArchiveEntry<'_>) -> Result<(), ExtractError>,
+{
+    let archive_file = std::fs::File::open(archive_path)?;
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(archive_file)?;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index)?;
+                let name = entry.name().to_string();
+                let mode = entry.unix_mode();
+                let compressed_size = Some(entry.compressed_size());
+                let kind = if entry.is_dir() {
+                    EntryKind::Directory
+                } else if mode.map(|mode| mode & S_IFMT == S_IFLNK).unwrap_or(false) {
+                    let mut target = Vec::new();
+                    copy_limited(&mut entry, &mut target, MAX_SYMLINK_TARGET_LENGTH)?
+                        .ok_or_else(|| ExtractError::InvalidSymlink { entry: name.clone() })?;
+                    EntryKind::Symlink(String::from_utf8(target).map_err(|_| ExtractError::InvalidSymlink { entry: name.clone() })?)
+                } else {
+                    EntryKind::File
+                };
+                visit(ArchiveEntry { name, kind, mode, compressed_size, reader: &mut entry })?;
+            }
+            Ok(())
+        },
+        ArchiveFormat::TarZstd => for_each_tar_entry(zstd::stream::read::Decoder::new(archive_file)?, visit),
+        ArchiveFormat::TarXz => for_each_tar_entry(xz2::read::XzDecoder::new(archive_file), visit),
+    }
+}
+
+fn for_each_tar_entry<R: Read, F>( reader: R, mut visit: F ) -> Result<(), ExtractError>
+where
+    F: FnMut(ArchiveEntry<'_>) -> Result<(), ExtractError>,
+{
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let mode = entry.header().mode().ok();
+        let kind = match entry.header().entry_type() {
+            tar::EntryType::Directory => EntryKind::Directory,
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            tar::EntryType::Symlink => {
+                let target = entry.link_name_bytes().map(|target| String::from_utf8_lossy(&target).to_string()).unwrap_or_default();
+                EntryKind::Symlink(target)
+            },
+            tar::EntryType::XGlobalHeader => continue,
+            // Hard links, devices and fifos have no business in a package
+            _ => return Err(ExtractError::UnsupportedEntry { entry: name }),
+        };
+        visit(ArchiveEntry { name, kind, mode, compressed_size: None, reader: &mut entry })?;
+    }
+    Ok(())
+}
+
+pub fn extract_archive( archive_path: &Path, format: ArchiveFormat, target_directory: &Path, limits: &ExtractLimits ) -> Result<(), ExtractError> {
+    let archive_size = std::fs::metadata(archive_path)?.len();
+    std::fs::create_dir_all(target_directory)?;
+
+    let mut entry_count: usize = 0;
+    let mut total_size: u64 = 0;
+    for_each_entry(archive_path, format, |entry| {
+        entry_count += 1;
+        if entry_count > limits.max_entries {
+            return Err(ExtractError::TooManyEntries { limit: limits.max_entries });
+        }
+        let name = entry.name;
+        let relative_path = match entry_path(&name)? {
+            Some(relative_path) => relative_path,
+            None => return Ok(()),
+        };
+        // A symlink entry replaces a symlink at its own path without following it
+        if through_symlink(target_directory, &relative_path, !matches!(entry.kind, EntryKind::Symlink(_))) {
+            return Err(ExtractError::ThroughSymlink { entry: name });
+        }
+        let output_path = target_directory.join(&relative_path);
+
+        if let EntryKind::Directory = entry.kind {
+            std::fs::create_dir_all(&output_path)?;
+            return Ok(());
+        }
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        if let EntryKind::Symlink(target) = entry.kind {
+            if !symlink_stays_inside(&relative_path, &target) {
+                return Err(ExtractError::SymlinkEscapes { entry: name, target });
+            }
+            create_symlink(&target, &output_path, &name)?;
+            return Ok(());
+        }
+
+        // The sizes in the archive can lie, the limits are applied to what actually comes out of the decompressor.
+        // Tar archives are compressed as a whole so they are held to the ratio as a whole
+        let remaining = limits.max_total_size - total_size;
+        let ratio_limit = match entry.compressed_size {
+            Some(compressed_size) => compressed_size.saturating_mul(limits.max_compression_ratio).max(RATIO_CHECK_MIN_SIZE),
+            None => archive_size.saturating_mul(limits.max_compression_ratio).max(RATIO_CHECK_MIN_SIZE).saturating_sub(total_size),
+        };
+        let mut output_file = std::fs::File::create(&output_path)?;
+        let written = copy_limited(entry.reader, &mut output_file, remaining.min(ratio_limit));
+        drop(output_file);
+        let written = match written {
+            Ok(Some(written)) => written,
+            Ok(None) => {
+                let _ = std::fs::remove_file(&output_path);
+                return Err(if ratio_limit < remaining {
+                    ExtractError::CompressionRatio { entry: name, limit: limits.max_compression_ratio }
+                } else {
+                    ExtractError::TooLarge { entry: name, limit: limits.max_total_size }
+                });
+            },
+            Err(e) => {
+                let _ = std::fs::remove_file(&output_path);
+                return Err(e.into());
+            }
+        };
+        total_size += written;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&output_path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(unix)]
+fn create_symlink( target: &str, link_path: &Path, _name: &str ) -> Result<(), ExtractError> {
+    if link_path.symlink_metadata().is_ok() {
+        std::fs::remove_file(link_path)?;
+    }
+    std::os::unix::fs::symlink(target, link_path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_symlink( _target: &str, _link_path: &Path, name: &str ) -> Result<(), ExtractError> {
+    Err(ExtractError::UnsupportedSymlink { entry: name.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory under the system temp directory, unique to the test
+    fn test_directory( name: &str ) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("syntax-extract-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // Entries are (name, symlink target, contents)
+    fn write_zip( path: &Path, entries: &[(&str, Option<&str>, &[u8])] ) {
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, target, contents) in entries {
+            match target {
+                Some(target) => writer.add_symlink(*name, *target, zip::write::FileOptions::default()).unwrap(),
+                None => {
+                    writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+                    writer.write_all(contents).unwrap();
+                }
+            }
+        }
+        writer.finish().unwrap();
+    }
+
+    // Entries are (name, entry type, link target, contents). Names and link targets are written as is, the tar
+    // crate refuses to write the unsafe ones itself
+    fn write_tar( path: &Path, format: ArchiveFormat, entries: &[(&str, tar::EntryType, &str, &[u8])] ) {
+        let file = std::fs::File::create(path).unwrap();
+        let writer: Box<dyn Write> = match format {
+            ArchiveFormat::TarZstd => Box::new(zstd::stream::write::Encoder::new(file, 0).unwrap().auto_finish()),
+            ArchiveFormat::TarXz => Box::new(xz2::write::XzEncoder::new(file, 6)),
+            ArchiveFormat::Zip => unreachable!("zip archives are written by write_zip"),
+        };
+        let mut builder = tar::Builder::new(writer);
+        for (name, entry_type, target, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *contents).unwrap();
+        }
+        builder.into_inner().unwrap().flush().unwrap();
+    }
+
+    fn extract_tar( directory: &Path, archive_name: &str, format: ArchiveFormat, entries: &[(&str, tar::EntryType, &str, &[u8])] ) -> Result<(), ExtractError> {
+        let archive_path = directory.join(archive_name);
+        write_tar(&archive_path, format, entries);
+        extract_archive(&archive_path, format, &directory.join("target"), &ExtractLimits::default())
+    }
+
+    fn extract( directory: &Path, archive_name: &str, entries: &[(&str, Option<&str>, &[u8])] ) -> Result<(), ExtractError> {
+        let archive_path = directory.join(archive_name);
+        write_zip(&archive_path, entries);
+        extract_archive(&archive_path, ArchiveFormat::Zip, &directory.join("target"), &ExtractLimits::default())
+    }
+
+    #[test]
+    fn entry_paths_stay_inside_the_target() {
+        assert_eq!(entry_path("content/fonts/a.ttf").unwrap(), Some(PathBuf::from("content/fonts/a.ttf")));
+        assert_eq!(entry_path("./content\\sky/./b.tex").unwrap(), Some(PathBuf::from("content/sky/b.tex")));
+        assert_eq!(entry_path("./").unwrap(), None);
+        for name in ["../a", "a/../../b", "a/..", "/etc/passwd", "\\windows\\a"] {
+            assert!(matches!(entry_path(name), Err(ExtractError::UnsafePath { .. })), "{}", name);
+        }
+    }
+
+    #[test]
+    fn symlinks_may_only_point_inside() {
+        assert!(symlink_stays_inside(Path::new("lib/a.so"), "b.so"));
+        assert!(symlink_stays_inside(Path::new("lib/a.so"), "../lib64/./b.so"));
+        assert!(!symlink_stays_inside(Path::new("lib/a.so"), "../../b.so"));
+        assert!(!symlink_stays_inside(Path::new("a.so"), "/usr/lib/a.so"));
+        // A name followed by .. could be a symlink itself once the link is followed
+        assert!(!symlink_stays_inside(Path::new("lib/a.so"), "d/l/../b.so"));
+    }
+
+    #[test]
+    fn extracts_files_and_directories() {
+        let directory = test_directory("files");
+        extract(&directory, "a.zip", &[("content/", None, b""), ("content/fonts/a.ttf", None, b"font"), ("./b.txt", None, b"b")]).unwrap();
+        assert_eq!(std::fs::read(directory.join("target/content/fonts/a.ttf")).unwrap(), b"font");
+        assert_eq!(std::fs::read(directory.join("target/b.txt")).unwrap(), b"b");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_entries_outside_the_target() {
+        let directory = test_directory("outside");
+        assert!(matches!(extract(&directory, "a.zip", &[("../evil", None, b"x")]), Err(ExtractError::UnsafePath { .. })));
+        assert!(!directory.join("evil").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlink_escapes() {
+        let directory = test_directory("symlinks");
+        assert!(matches!(extract(&directory, "a.zip", &[("l", Some("../.."), b"")]), Err(ExtractError::SymlinkEscapes { .. })));
+        // The same archive writing through its own symlink
+        assert!(matches!(extract(&directory, "b.zip", &[("d/l", Some(".."), b""), ("d/l/x", None, b"x")]), Err(ExtractError::ThroughSymlink { .. })));
+        // A later archive writing through a symlink an earlier one left behind
+        assert!(matches!(extract(&directory, "c.zip", &[("d/l/q", Some("../.."), b"")]), Err(ExtractError::ThroughSymlink { .. })));
+        // Or overwriting one, which would write wherever it points
+        std::os::unix::fs::symlink(&directory, directory.join("target/f")).unwrap();
+        assert!(matches!(extract(&directory, "d.zip", &[("f", None, b"x")]), Err(ExtractError::ThroughSymlink { .. })));
+        assert!(!directory.join("x").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn refuses_oversized_symlink_targets() {
+        let directory = test_directory("long-symlink");
+        let target = "a/".repeat(MAX_SYMLINK_TARGET_LENGTH as usize);
+        assert!(matches!(extract(&directory, "a.zip", &[("l", Some(&target), b"")]), Err(ExtractError::InvalidSymlink { .. })));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn enforces_the_limits() {
+        let directory = test_directory("limits");
+        let archive_path = directory.join("a.zip");
+        write_zip(&archive_path, &[("a", None, &[0; 4096]), ("b", None, &[0; 4096])]);
+        let limits = ExtractLimits { max_entries: 1, ..ExtractLimits::default() };
+        assert!(matches!(extract_archive(&archive_path, ArchiveFormat::Zip, &directory.join("entries"), &limits), Err(ExtractError::TooManyEntries { .. })));
+        let limits = ExtractLimits { max_total_size: 6000, ..ExtractLimits::default() };
+        assert!(matches!(extract_archive(&archive_path, ArchiveFormat::Zip, &directory.join("size"), &limits), Err(ExtractError::TooLarge { .. })));
+
+        write_zip(&archive_path, &[("bomb", None, &vec![0; 4 * RATIO_CHECK_MIN_SIZE as usize])]);
+        let limits = ExtractLimits { max_compression_ratio: 10, ..ExtractLimits::default() };
+        assert!(matches!(extract_archive(&archive_path, ArchiveFormat::Zip, &directory.join("ratio"), &limits), Err(ExtractError::CompressionRatio { .. })));
+        assert!(!directory.join("ratio/bomb").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn checks_tar_archives_the_same() {
+        use tar::EntryType::{Link, Regular, Symlink};
+        for format in [ArchiveFormat::TarZstd, ArchiveFormat::TarXz] {
+            let directory = test_directory(&format!("tar-{:?}", format));
+            extract_tar(&directory, "a.tar", format, &[("content/fonts/a.ttf", Regular, "", b"font"), ("lib/l", Symlink, "../content", b"")]).unwrap();
+            assert_eq!(std::fs::read(directory.join("target/content/fonts/a.ttf")).unwrap(), b"font");
+
+            assert!(matches!(extract_tar(&directory, "b.tar", format, &[("../evil", Regular, "", b"x")]), Err(ExtractError::UnsafePath { .. })));
+            assert!(matches!(extract_tar(&directory, "c.tar", format, &[("/tmp/evil", Regular, "", b"x")]), Err(ExtractError::UnsafePath { .. })));
+            assert!(!directory.join("evil").exists());
+            assert!(matches!(extract_tar(&directory, "d.tar", format, &[("l", Symlink, "../..", b"")]), Err(ExtractError::SymlinkEscapes { .. })));
+            assert!(matches!(extract_tar(&directory, "e.tar", format, &[("h", Link, "/etc/passwd", b"")]), Err(ExtractError::UnsupportedEntry { .. })));
+            #[cfg(unix)]
+            assert!(matches!(extract_tar(&directory, "f.tar", format, &[("lib/l/x", Regular, "", b"x")]), Err(ExtractError::ThroughSymlink { .. })));
+
+            let archive_path = directory.join("bomb.tar");
+            write_tar(&archive_path, format, &[("bomb", Regular, "", &vec![0; 4 * RATIO_CHECK_MIN_SIZE as usize])]);
+            let limits = ExtractLimits { max_compression_ratio: 10, ..ExtractLimits::default() };
+            assert!(matches!(extract_archive(&archive_path, format, &directory.join("ratio"), &limits), Err(ExtractError::CompressionRatio { .. })));
+            assert!(!directory.join("ratio/bomb").exists());
+            std::fs::remove_dir_all(&directory).unwrap();
+        }
+    }
+}
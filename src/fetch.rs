@@ -0,0 +1,138 @@
+use colored::*;
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+
+use crate::cache::PackageCache;
+use crate::delta;
+use crate::download::{download_each, verify_sha256, DownloadError, DownloadRequest};
+use crate::manifest::{Manifest, Package, Patch};
+use crate::mirrors::Mirrors;
+use crate::retry::RetryPolicy;
+use crate::{debug, error, info};
+
+// Gets every package of a manifest into the package cache, in order of preference:
+// already cached, rebuilt from a cached older build plus a patch, or downloaded in full
+pub struct PackageFetcher<'a> {
+    pub client: &'a Client,
+    pub mirrors: &'a Mirrors,
+    pub retry_policy: &'a RetryPolicy,
+    pub cache: &'a PackageCache,
+    pub downloads_directory: &'a Path,
+    pub concurrency: usize,
+}
+
+fn package_request( package: &Package, version: &str ) -> DownloadRequest {
+    DownloadRequest { path: package.path(version), size: Some(package.size), sha256: package.sha256.clone() }
+}
+
+fn patch_request( patch: &Patch, version: &str ) -> DownloadRequest {
+    DownloadRequest { path: patch.path(version), size: Some(patch.size), sha256: patch.sha256.clone() }
+}
+
+impl<'a> PackageFetcher<'a> {
+    // Returns the cached path of every package, in manifest order
+    pub async fn fetch( &self, manifest: &Manifest ) -> Result<Vec<PathBuf>, DownloadError> {
+        let mut package_paths : Vec<Option<PathBuf>> = vec![None; manifest.packages.len()];
+        self.fetch_each(manifest, |index, path| {
+            package_paths[index] = Some(path);
+            Ok::<(), DownloadError>(())
+        }).await?;
+        Ok(package_paths.into_iter().map(|path| path.expect("every package is either cached, patched or downloaded")).collect())
+    }
+
+    // Hands the cached path of every package to `on_ready` together with its index in the manifest as soon as it
+    // is there, packages that were already cached first and the rest as their downloads finish
+    pub async fn fetch_each<E, F>( &self, manifest: &Manifest, mut on_ready: F ) -> Result<(), E>
+    where
+        E: From<DownloadError>,
+        F: FnMut(usize, PathBuf) -> Result<(), E>,
+    {
+        let version = manifest.version.as_str();
+        let mut cached : Vec<(usize, PathBuf)> = Vec::new();
+        let mut patches : Vec<(usize, &Patch, PathBuf)> = Vec::new();
+        let mut full_downloads : Vec<usize> = Vec::new();
+        for (index, package) in manifest.packages.iter().enumerate() {
+            if let Some(cached_path) = self.cache.get(&package.sha256) {
+                debug(&format!("Using cached {}", package.name.bright_blue()));
+                cached.push((index, cached_path));
+                continue;
+            }
+            let patchable = package.patches.iter().find_map(|patch| self.cache.get(&patch.from).map(|base_path| (patch, base_path)));
+            match patchable {
+                Some((patch, base_path)) => patches.push((index, patch, base_path)),
+                None => full_downloads.push(index),
+            }
+        }
+        info(&format!("{} packages up to date, {} to patch, {} to download", cached.len(), patches.len(), full_downloads.len()));
+        for (index, cached_path) in cached {
+            on_ready(index, cached_path)?;
+        }
+
+        let mut requests = patches.iter().map(|(_, patch, _)| patch_request(patch, version)).collect::<Vec<DownloadRequest>>();
+        requests.extend(full_downloads.iter().map(|index| package_request(&manifest.packages[*index], version)));
+        let mut fallback_downloads : Vec<usize> = Vec::new();
+        // Patching reads and writes whole packages, it runs on the blocking pool while the downloads go on and the
+        // results are handed over here as they come in
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<(usize, PathBuf)>();
+        let downloads = download_each(self.client, self.mirrors, &requests, self.downloads_directory, self.concurrency, self.retry_policy, move |request_index, downloaded_path| {
+            // Only fails once the receiving side gave up, which cancels the downloads as well
+            let _ = sender.send((request_index, downloaded_path));
+            Ok::<(), E>(())
+        });
+        let downloaded = async {
+            while let Some((request_index, downloaded_path)) = receiver.recv().await {
+                let Some((index, patch, base_path)) = patches.get(request_index) else {
+                    let index = full_downloads[request_index - patches.len()];
+                    let cached_path = self.cache.insert(&downloaded_path, &manifest.packages[index].sha256).map_err(|e| E::from(e.into()))?;
+                    on_ready(index, cached_path)?;
+                    continue;
+                };
+                let package = &manifest.packages[*index];
+                match self.apply_patch(package, base_path, downloaded_path).await {
+                    Ok(cached_path) => {
+                        info(&format!("Patched {} from {}", package.name.bright_blue(), patch.from.bright_blue()));
+                        on_ready(*index, cached_path)?;
+                    },
+                    Err(e) => {
+                        error(&format!("Failed to patch {}: {}, downloading the full package instead", package.name.bright_blue(), e.to_string().bright_red()));
+                        fallback_downloads.push(*index);
+                    }
+                }
+            }
+            Ok::<(), E>(())
+        };
+        tokio::try_join!(downloads, downloaded)?;
+
+        if !fallback_downloads.is_empty() {
+            let requests = fallback_downloads.iter().map(|index| package_request(&manifest.packages[*index], version)).collect::<Vec<DownloadRequest>>();
+            download_each(self.client, self.mirrors, &requests, self.downloads_directory, self.concurrency, self.retry_policy, |request_index, downloaded_path| {
+                let index = fallback_downloads[request_index];
+                let cached_path = self.cache.insert(&downloaded_path, &manifest.packages[index].sha256).map_err(|e| E::from(e.into()))?;
+                on_ready(index, cached_path)
+            }).await?;
+        }
+        Ok(())
+    }
+
+    // Rebuilds the package from the cached `base_path` and the downloaded patch, which is removed afterwards
+    async fn apply_patch( &self, package: &Package, base_path: &Path, patch_path: PathBuf ) -> Result<PathBuf, DownloadError> {
+        let cache = self.cache.clone();
+        let package = package.clone();
+        let base_path = base_path.to_path_buf();
+        let patched_path = self.downloads_directory.join(format!("{}.patched", package.sha256));
+        tokio::task::spawn_blocking(move || {
+            let patched = patch_package(&cache, &package, &base_path, &patch_path, &patched_path);
+            let _ = std::fs::remove_file(&patch_path);
+            patched
+        }).await.expect("patching does not panic")
+    }
+}
+
+fn patch_package( cache: &PackageCache, package: &Package, base_path: &Path, patch_path: &Path, patched_path: &Path ) -> Result<PathBuf, DownloadError> {
+    delta::apply_patch(base_path, patch_path, patched_path)?;
+    if let Err(e) = verify_sha256(patched_path, &package.name, &package.sha256) {
+        let _ = std::fs::remove_file(patched_path);
+        return Err(e);
+    }
+    Ok(cache.insert(patched_path, &package.sha256)?)
+}
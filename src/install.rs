@@ -0,0 +1,228 @@
+use colored::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use crate::download::DownloadError;
+use crate::extract::{self, ArchiveFormat, ExtractError, ExtractLimits};
+use crate::cache::PackageCache;
+use crate::fetch::PackageFetcher;
+use crate::inventory::{self, InstalledFile};
+use crate::manifest::{Manifest, Package};
+use crate::{debug, info};
+
+// Versions/<version>.staging holds an install in progress, Versions/<version>.old the install it is replacing
+const STAGING_SUFFIX: &str = ".staging";
+const BACKUP_SUFFIX: &str = ".old";
+
+#[derive(Debug)]
+pub enum InstallError {
+    Io(std::io::Error),
+    Download(DownloadError),
+    Extract { package: String, error: ExtractError },
+    MissingFile(PathBuf),
+    NotInManifest(String),
+}
+
+impl std::fmt::Display for InstallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstallError::Io(e) => write!(f, "IO error: {}", e),
+            InstallError::Download(e) => write!(f, "{}", e),
+            InstallError::Extract { package, error } => write!(f, "failed to extract {}: {}", package, error),
+            InstallError::MissingFile(path) => write!(f, "{} is missing after extraction", path.to_str().unwrap()),
+            InstallError::NotInManifest(package) => write!(f, "{} is not in the installed manifest", package),
+        }
+    }
+}
+
+impl From<std::io::Error> for InstallError {
+    fn from(e: std::io::Error) -> Self {
+        InstallError::Io(e)
+    }
+}
+
+impl From<DownloadError> for InstallError {
+    fn from(e: DownloadError) -> Self {
+        InstallError::Download(e)
+    }
+}
+
+pub fn staging_directory( versions_directory: &Path, version: &str ) -> PathBuf {
+    versions_directory.join(format!("{}{}", version, STAGING_SUFFIX))
+}
+
+// Staging and backup directories are never a usable install
+pub fn is_transient_directory( path: &Path ) -> bool {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    name.ends_with(STAGING_SUFFIX) || name.ends_with(BACKUP_SUFFIX)
+}
+
+// Starts from an empty staging directory, anything left in there is from an install that did not finish
+pub fn prepare_staging( staging_directory: &Path ) -> Result<(), InstallError> {
+    if staging_directory.exists() {
+        debug(&format!("Removing unfinished install at {}", staging_directory.to_str().unwrap().bright_blue()));
+        std::fs::remove_dir_all(staging_directory)?;
+    }
+    std::fs::create_dir_all(staging_directory)?;
+    Ok(())
+}
+
+// Points the client at our website, it is the last file an install writes
+pub fn write_app_settings( version_directory: &Path, base_url: &str ) -> Result<(), std::io::Error> {
+    let app_settings_xml = format!(
+"<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Settings>
+	<ContentFolder>content</ContentFolder>
+	<BaseUrl>https://{}</BaseUrl>
+</Settings>", base_url
+    );
+    std::fs::write(version_directory.join("AppSettings.xml"), app_settings_xml)
+}
+
+pub fn extract_to_dir( archive_file : &Path, format : ArchiveFormat, target_dir : &Path ) -> Result<(), ExtractError> {
+    extract::extract_archive(archive_file, format, target_dir, &ExtractLimits::default())
+}
+
+// Fetches every package of `manifest` and extracts each one into `root_directory` as soon as it is there, while
+// the rest are still downloading. Packages are extracted in manifest order so a later package still overwrites the
+// files of an earlier one. Returns the files of every package, in manifest order
+pub async fn fetch_and_extract( fetcher: &PackageFetcher<'_>, manifest: &Manifest, root_directory: &Path ) -> Result<Vec<Vec<InstalledFile>>, InstallError> {
+    let (sender, receiver) = std::sync::mpsc::channel::<(usize, PathBuf)>();
+    let packages = manifest.packages.clone();
+    let extract_directory = root_directory.to_path_buf();
+    let cache = fetcher.cache.clone();
+    let extractor = tokio::task::spawn_blocking(move || extract_in_order(&packages, receiver, &extract_directory, &cache));
+    let fetched = fetcher.fetch_each(manifest, |index, path| {
+        // The extractor only hangs up after failing, its error is the one reported below
+        sender.send((index, path)).map_err(|_| InstallError::Io(std::io::Error::other("extraction stopped")))
+    }).await;
+    drop(sender);
+    let package_files = extractor.await.expect("the extractor does not panic")?;
+    fetched?;
+    Ok(package_files)
+}
+
+// Extracts packages as they arrive, holding back the ones that arrive before the packages listed ahead of them.
+// A package is done with once it is extracted and its files are recorded, so the cache is brought back under its
+// size limit right away, counting the files extracted so far against it. Together the cache and the install take up
+// about as much disk space as the cache alone is allowed to, unless the install is larger than that by itself. The
+// packages still to come and the builds their patches start from are never evicted
+fn extract_in_order( packages: &[Package], receiver: Receiver<(usize, PathBuf)>, root_directory: &Path, cache: &PackageCache ) -> Result<Vec<Vec<InstalledFile>>, InstallError> {
+    let mut package_paths : Vec<Option<PathBuf>> = vec![None; packages.len()];
+    let mut package_files : Vec<Vec<InstalledFile>> = Vec::with_capacity(packages.len());
+    let mut extracted_size : u64 = 0;
+    for (index, package_path) in receiver {
+        package_paths[index] = Some(package_path);
+        while let Some(Some(package_path)) = package_paths.get(package_files.len()) {
+            let package = &packages[package_files.len()];
+            let target_directory = package.target_directory(root_directory);
+            debug(&format!("Extracting {} to {}", package.name.bright_blue(), target_directory.to_str().unwrap().bright_blue()));
+            extract_to_dir(package_path, package.format(), &target_directory).map_err(|error| InstallError::Extract { package: package.name.clone(), error })?;
+            let files = inventory::package_files(&package.name, package.format(), package_path)?;
+            extracted_size += files.iter().map(|file| file.size).sum::<u64>();
+            package_files.push(files);
+
+            let pending = packages[package_files.len()..].iter()
+                .flat_map(|package| std::iter::once(&package.sha256).chain(package.patches.iter().map(|patch| &patch.from)))
+                .map(|sha256| sha256.to_ascii_lowercase())
+                .collect::<HashSet<String>>();
+            cache.evict_leaving_room(&pending, extracted_size)?;
+        }
+    }
+    Ok(package_files)
+}
+
+// Adds a single package to an existing install. It is extracted next to `target_directory` first so a failed
+// extraction never leaves a half filled directory behind
+pub fn install_package( package: &Package, package_path: &Path, target_directory: &Path ) -> Result<(), InstallError> {
+    let mut staging_name = target_directory.file_name().unwrap().to_os_string();
+    staging_name.push(STAGING_SUFFIX);
+    let staging_directory = target_directory.with_file_name(staging_name);
+    prepare_staging(&staging_directory)?;
+    debug(&format!("Extracting {} to {}", package.name.bright_blue(), target_directory.to_str().unwrap().bright_blue()));
+    if let Err(error) = extract_to_dir(package_path, package.format(), &staging_directory) {
+        let _ = std::fs::remove_dir_all(&staging_directory);
+        return Err(InstallError::Extract { package: package.name.clone(), error });
+    }
+    if target_directory.exists() {
+        std::fs::remove_dir_all(target_directory)?;
+    }
+    std::fs::rename(&staging_directory, target_directory)?;
+    Ok(())
+}
+
+// Makes sure the staged install has everything we expect before it replaces anything
+pub fn verify_staging( staging_directory: &Path, packages: &[Package], required_files: &[&str] ) -> Result<(), InstallError> {
+    for package in packages {
+        let target_directory = package.target_directory(staging_directory);
+        if !target_directory.is_dir() {
+            return Err(InstallError::MissingFile(target_directory));
+        }
+    }
+    for required_file in required_files {
+        let path = staging_directory.join(required_file);
+        if !path.is_file() {
+            return Err(InstallError::MissingFile(path));
+        }
+    }
+    Ok(())
+}
+
+// Swaps the staged install in for the version directory. The running bootstrapper lives in the version directory
+// so it is copied into the staged install first. On Windows the version directory can not be renamed while the
+// bootstrapper is running from it, in that case the staged files are moved over one by one instead
+pub fn commit_staging( staging_directory: &Path, version_directory: &Path, current_exe_path: &Path ) -> Result<(), InstallError> {
+    if current_exe_path.parent() == Some(version_directory) {
+        let staged_exe_path = staging_directory.join(current_exe_path.file_name().unwrap());
+        if !staged_exe_path.exists() {
+            std::fs::copy(current_exe_path, &staged_exe_path)?;
+        }
+    }
+
+    if !version_directory.exists() {
+        std::fs::rename(staging_directory, version_directory)?;
+        return Ok(());
+    }
+
+    let mut backup_name = version_directory.file_name().unwrap().to_os_string();
+    backup_name.push(BACKUP_SUFFIX);
+    let backup_directory = version_directory.with_file_name(backup_name);
+    if backup_directory.exists() {
+        std::fs::remove_dir_all(&backup_directory)?;
+    }
+    match std::fs::rename(version_directory, &backup_directory) {
+        Ok(()) => {
+            if let Err(e) = std::fs::rename(staging_directory, version_directory) {
+                std::fs::rename(&backup_directory, version_directory)?;
+                return Err(e.into());
+            }
+            let _ = std::fs::remove_dir_all(&backup_directory);
+        },
+        Err(e) => {
+            debug(&format!("Could not move {} aside ({}), moving the staged files instead", version_directory.to_str().unwrap().bright_blue(), e));
+            for entry in std::fs::read_dir(version_directory)? {
+                let path = entry?.path();
+                if path == current_exe_path {
+                    continue;
+                }
+                if path.is_dir() {
+                    std::fs::remove_dir_all(&path)?;
+                } else {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+            for entry in std::fs::read_dir(staging_directory)? {
+                let path = entry?.path();
+                let target_path = version_directory.join(path.file_name().unwrap());
+                if target_path == current_exe_path {
+                    continue;
+                }
+                std::fs::rename(&path, &target_path)?;
+            }
+            std::fs::remove_dir_all(staging_directory)?;
+        }
+    }
+    info(&format!("Installed {}", version_directory.file_name().unwrap().to_string_lossy().bright_blue()));
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path};
+
+use crate::download::sha256_file;
+use crate::extract::{self, ArchiveEntry, ArchiveFormat, EntryKind, ExtractError};
+use crate::fetch::PackageFetcher;
+use crate::install::InstallError;
+use crate::manifest::Manifest;
+use crate::state::{InstallState, InstalledPackage};
+use crate::{debug, info};
+
+// A file a package put into the version directory, see InstallState
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstalledFile {
+    // Relative to the package directory, always separated by /
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// "content/./fonts" -> "content/fonts", the same on every platform
+pub fn inventory_path( path: &Path ) -> String {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn archive_error( package_name: &str, error: ExtractError ) -> InstallError {
+    InstallError::Extract { package: package_name.to_string(), error }
+}
+
+// The inventory path of an entry, None for directories and symlinks since only regular files are recorded
+fn file_entry_path( entry: &ArchiveEntry ) -> Result<Option<String>, ExtractError> {
+    if !matches!(entry.kind, EntryKind::File) {
+        return Ok(None);
+    }
+    Ok(extract::entry_path(&entry.name)?.map(|path| inventory_path(&path)))
+}
+
+// Every file in a package, hashed straight from the archive so the install state describes what the package holds
+// and not whatever ended up on disk
+pub fn package_files( package_name: &str, format: ArchiveFormat, package_path: &Path ) -> Result<Vec<InstalledFile>, InstallError> {
+    let mut files = Vec::new();
+    extract::for_each_entry(package_path, format, |entry| {
+        if let Some(path) = file_entry_path(&entry)? {
+            let mut hasher = Sha256::new();
+            let size = std::io::copy(entry.reader, &mut hasher)?;
+            files.push(InstalledFile { path, size, sha256: format!("{:x}", hasher.finalize()) });
+        }
+        Ok(())
+    }).map_err(|error| archive_error(package_name, error))?;
+    Ok(files)
+}
+
+fn is_intact( path: &Path, file: &InstalledFile ) -> bool {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() == file.size => {
+            sha256_file(path).map(|found| found.eq_ignore_ascii_case(&file.sha256)).unwrap_or(false)
+        },
+        _ => false,
+    }
+}
+
+// Files that are missing or no longer match what their package put there
+pub fn damaged_files<'a>( state: &'a InstallState, version_directory: &Path ) -> Vec<(&'a InstalledPackage, &'a InstalledFile)> {
+    state.packages.iter()
+        .flat_map(|package| package.files.iter().map(move |file| (package, file)))
+        .filter(|(package, file)| !is_intact(&version_directory.join(package.full_path(file)), file))
+        .collect()
+}
+
+// Writes the given files of a package, each one goes to a temporary file first so an interrupted repair never
+// leaves a truncated file behind
+fn extract_files( package_name: &str, format: ArchiveFormat, package_path: &Path, files: &[&InstalledFile], target_directory: &Path ) -> Result<(), InstallError> {
+    let mut remaining = files.iter().map(|file| (file.path.as_str(), file.size)).collect::<HashMap<&str, u64>>();
+    extract::for_each_entry(package_path, format, |entry| {
+        let (path, size) = match file_entry_path(&entry)?.and_then(|path| remaining.remove_entry(path.as_str())) {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        // The rename below replaces a symlink at the path itself, one further up would be followed
+        if extract::through_symlink(target_directory, Path::new(path), false) {
+            return Err(ExtractError::ThroughSymlink { entry: entry.name });
+        }
+        let target_path = target_directory.join(path);
+        std::fs::create_dir_all(target_path.parent().unwrap())?;
+        let mut temporary_name = target_path.file_name().unwrap().to_os_string();
+        temporary_name.push(".repair");
+        let temporary_path = target_path.with_file_name(temporary_name);
+        // The inventory knows how large the file is, anything beyond that is not what was installed
+        if extract::copy_limited(entry.reader, &mut std::fs::File::create(&temporary_path)?, size)?.is_none() {
+            let _ = std::fs::remove_file(&temporary_path);
+            return Err(ExtractError::TooLarge { entry: entry.name, limit: size });
+        }
+        #[cfg(unix)]
+        if let Some(mode) = entry.mode {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&temporary_path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        std::fs::rename(&temporary_path, &target_path)?;
+        debug(&format!("Restored {} from {}", path.bright_blue(), package_name.bright_blue()));
+        Ok(())
+    }).map_err(|error| archive_error(package_name, error))?;
+    match remaining.into_keys().next() {
+        Some(path) => Err(InstallError::MissingFile(target_directory.join(path))),
+        None => Ok(()),
+    }
+}
+
+// Hashes the install against its state and puts back only the files that are missing or modified, taken from
+// the package cache or downloaded again. Returns the repaired files relative to the version directory
+pub async fn repair( state: &InstallState, manifest: &Manifest, fetcher: &PackageFetcher<'_>, version_directory: &Path ) -> Result<Vec<String>, InstallError> {
+    info(&format!("Checking {} installed files", state.file_count().to_string().bright_blue()));
+    let damaged = damaged_files(state, version_directory);
+    if damaged.is_empty() {
+        return Ok(Vec::new());
+    }
+    let package_names = damaged.iter().map(|(package, _)| package.name.as_str()).collect::<HashSet<&str>>();
+    let damaged_manifest = manifest.subset(|package| package_names.contains(package.name.as_str()));
+    if let Some(missing) = package_names.iter().find(|name| !damaged_manifest.packages.iter().any(|package| package.name == **name)) {
+        return Err(InstallError::NotInManifest(missing.to_string()));
+    }
+    info(&format!("{} files are missing or modified, restoring them from {} packages", damaged.len().to_string().bright_blue(), damaged_manifest.packages.len().to_string().bright_blue()));
+
+    let package_paths = fetcher.fetch(&damaged_manifest).await?;
+    let mut repaired = Vec::new();
+    for (package, package_path) in damaged_manifest.packages.iter().zip(package_paths) {
+        let package_files = damaged.iter().find(|(package_files, _)| package_files.name == package.name).unwrap().0;
+        let files = damaged.iter().filter(|(package_files, _)| package_files.name == package.name).map(|(_, file)| *file).collect::<Vec<&InstalledFile>>();
+        extract_files(&package.name, package.format(), &package_path, &files, &version_directory.join(&package_files.directory))?;
+        repaired.extend(files.iter().map(|file| package_files.full_path(file)));
+    }
+    Ok(repaired)
+}
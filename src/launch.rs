@@ -0,0 +1,495 @@
+// syntax-player:// launch URIs look like
+// syntax-player://1+launchmode:play+gameinfo:TICKET+placelauncherurl:https%3A%2F%2Fwww.syntax.eco%2FGame%2Fplacelauncher.ashx%3FplaceId%3D660+k:l
+// or, as the site writes them, with placelauncherurl left unencoded
+// syntax-player://1+launchmode:play+gameinfo:TICKET+placelauncherurl:https://www.syntax.eco/Game/placelauncher.ashx?placeId=660&t=TICKET
+// The first field is the protocol version, every other field is a key:value pair with a percent-encoded value, except
+// for urls written out as is, which are kept verbatim since their query may hold escapes of its own
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+pub const SCHEME: &str = "syntax-player";
+pub const PROTOCOL_VERSION: &str = "1";
+
+#[derive(Debug)]
+pub enum LaunchRequestError {
+    InvalidScheme,
+    MissingVersion,
+    UnsupportedVersion(String),
+    MalformedField(String),
+    InvalidEncoding(String),
+    DuplicateKey(String),
+    MissingKey(&'static str),
+    UnknownLaunchMode(String),
+}
+
+impl std::fmt::Display for LaunchRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LaunchRequestError::InvalidScheme => write!(f, "not a {}:// uri", SCHEME),
+            LaunchRequestError::MissingVersion => write!(f, "the uri has no protocol version"),
+            LaunchRequestError::UnsupportedVersion(version) => write!(f, "unsupported protocol version {}, expected {}", version, PROTOCOL_VERSION),
+            LaunchRequestError::MalformedField(field) => write!(f, "field {} is not a key:value pair", field),
+            LaunchRequestError::InvalidEncoding(key) => write!(f, "the value of {} is not valid percent-encoded UTF-8", key),
+            LaunchRequestError::DuplicateKey(key) => write!(f, "{} is given more than once", key),
+            LaunchRequestError::MissingKey(key) => write!(f, "{} is missing", key),
+            LaunchRequestError::UnknownLaunchMode(mode) => write!(f, "unknown launch mode {}", mode),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchRequest {
+    pub launch_mode: String,
+    pub ticket: Option<String>,
+    pub join_script_url: Option<String>,
+    pub client_year: Option<String>,
+    // Keys we do not know about, kept so a request serializes back to the same uri
+    pub extra: Vec<(String, String)>,
+}
+
+impl LaunchRequest {
+    pub fn parse( uri: &str ) -> Result<LaunchRequest, LaunchRequestError> {
+        let body = strip_scheme(uri.trim()).ok_or(LaunchRequestError::InvalidScheme)?;
+        // Some browsers append a slash to custom scheme uris
+        let body = body.strip_suffix('/').unwrap_or(body);
+        let mut fields = body.split('+');
+        let version = fields.next().filter(|version| !version.is_empty()).ok_or(LaunchRequestError::MissingVersion)?;
+        if version != PROTOCOL_VERSION {
+            return Err(LaunchRequestError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut launch_mode = None;
+        let mut ticket = None;
+        let mut join_script_url = None;
+        let mut client_year = None;
+        let mut extra: Vec<(String, String)> = Vec::new();
+        for field in fields {
+            if field.is_empty() {
+                continue;
+            }
+            let (key, value) = field.split_once(':').ok_or_else(|| LaunchRequestError::MalformedField(field.to_string()))?;
+            let key = percent_decode(key).ok_or_else(|| LaunchRequestError::InvalidEncoding(key.to_string()))?;
+            let value = decode_value(value).ok_or_else(|| LaunchRequestError::InvalidEncoding(key.clone()))?;
+            let slot = match key.as_str() {
+                "launchmode" => &mut launch_mode,
+                "gameinfo" => &mut ticket,
+                "placelauncherurl" => &mut join_script_url,
+                "clientyear" => &mut client_year,
+                _ => {
+                    if extra.iter().any(|(extra_key, _)| *extra_key == key) {
+                        return Err(LaunchRequestError::DuplicateKey(key));
+                    }
+                    extra.push((key, value));
+                    continue;
+                }
+            };
+            if slot.is_some() {
+                return Err(LaunchRequestError::DuplicateKey(key));
+            }
+            *slot = Some(value);
+        }
+
+        let launch_mode = launch_mode.ok_or(LaunchRequestError::MissingKey("launchmode"))?;
+        let mode = find_launch_mode(&launch_mode).ok_or_else(|| LaunchRequestError::UnknownLaunchMode(launch_mode.clone()))?;
+        if mode.uses(LaunchArgument::Ticket) && ticket.is_none() {
+            return Err(LaunchRequestError::MissingKey("gameinfo"));
+        }
+        if mode.uses(LaunchArgument::ScriptUrl) && join_script_url.is_none() {
+            return Err(LaunchRequestError::MissingKey("placelauncherurl"));
+        }
+        Ok(LaunchRequest { launch_mode, ticket, join_script_url, client_year, extra })
+    }
+
+    // Everything that gives away the ticket, logs hide these however they are percent-encoded, see redact_encoded
+    pub fn secrets( &self ) -> Vec<String> {
+        match &self.ticket {
+            Some(ticket) if !ticket.is_empty() => vec![ticket.clone()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// A piece of the client command line, the values come from the launch request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchArgument {
+    Literal(&'static str),
+    AuthenticationUrl,
+    // Handed over the way TicketPassing says, which may not end up on the command line at all
+    Ticket,
+    // placelauncherurl, the join script for play sessions and the place script for the others. It carries the ticket
+    // as t=TICKET, which is taken out unless the ticket is passed as an argument anyway
+    ScriptUrl,
+}
+
+pub struct LaunchMode {
+    pub names: &'static [&'static str],
+    pub description: &'static str,
+    pub executable: &'static str,
+    pub arguments: &'static [LaunchArgument],
+}
+
+impl LaunchMode {
+    pub fn uses( &self, argument: LaunchArgument ) -> bool {
+        self.arguments.contains(&argument)
+    }
+}
+
+// Every launch mode the bootstrapper knows, a uri picks one with launchmode:<name>
+pub const LAUNCH_MODES: &[LaunchMode] = &[
+    LaunchMode {
+        names: &["play"],
+        description: "SYNTAX",
+        executable: "SyntaxPlayerBeta.exe",
+        arguments: &[
+            LaunchArgument::Literal("--play"),
+            LaunchArgument::Literal("--authenticationUrl"), LaunchArgument::AuthenticationUrl,
+            LaunchArgument::Ticket,
+            LaunchArgument::Literal("--joinScriptUrl"), LaunchArgument::ScriptUrl,
+        ],
+    },
+    LaunchMode {
+        names: &["edit", "build"],
+        description: "SYNTAX Studio",
+        executable: "SyntaxStudioBeta.exe",
+        arguments: &[
+            LaunchArgument::Literal("--edit"),
+            LaunchArgument::Literal("--authenticationUrl"), LaunchArgument::AuthenticationUrl,
+            LaunchArgument::Ticket,
+            LaunchArgument::Literal("--scriptUrl"), LaunchArgument::ScriptUrl,
+        ],
+    },
+    LaunchMode {
+        names: &["playtest"],
+        description: "a local SYNTAX playtest",
+        executable: "SyntaxPlayerBeta.exe",
+        arguments: &[
+            LaunchArgument::Literal("--playtest"),
+            LaunchArgument::Literal("--scriptUrl"), LaunchArgument::ScriptUrl,
+        ],
+    },
+];
+
+pub fn find_launch_mode( name: &str ) -> Option<&'static LaunchMode> {
+    LAUNCH_MODES.iter().find(|mode| mode.names.iter().any(|mode_name| mode_name.eq_ignore_ascii_case(name)))
+}
+
+// Appends the arguments of the request's launch mode to the client command
+pub fn add_client_arguments( command: &mut Command, request: &LaunchRequest, authentication_url: &str, ticket_passing: TicketPassing, client_directory: &Path ) -> Result<TicketHandoff, std::io::Error> {
+    let mode = find_launch_mode(&request.launch_mode).expect("launch modes are checked while parsing");
+    let mut ticket_handoff = TicketHandoff { file: None, created: Instant::now() };
+    for argument in mode.arguments {
+        match argument {
+            LaunchArgument::Literal(value) => {
+                command.arg(value);
+            },
+            LaunchArgument::AuthenticationUrl => {
+                command.arg(authentication_url);
+            },
+            LaunchArgument::Ticket => {
+                ticket_handoff = hand_off_ticket(command, request.ticket.as_deref().unwrap_or_default(), ticket_passing, client_directory)?;
+            },
+            LaunchArgument::ScriptUrl => {
+                let script_url = request.join_script_url.as_deref().unwrap_or_default();
+                match (&request.ticket, ticket_passing) {
+                    (Some(ticket), TicketPassing::Environment | TicketPassing::File) => command.arg(without_ticket(script_url, ticket)),
+                    _ => command.arg(script_url),
+                };
+            },
+        }
+    }
+    Ok(ticket_handoff)
+}
+
+// Removes the query parameters that hold the ticket, t=TICKET or any other name
+fn without_ticket( url: &str, ticket: &str ) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let mut stripped = match url.split_once('?') {
+        Some((path, query)) => {
+            let parameters = query.split('&')
+                .filter(|parameter| {
+                    let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+                    !key.eq_ignore_ascii_case("t") && percent_decode(value).as_deref() != Some(ticket)
+                })
+                .collect::<Vec<&str>>();
+            if parameters.is_empty() {
+                path.to_string()
+            } else {
+                format!("{}?{}", path, parameters.join("&"))
+            }
+        },
+        None => url.to_string(),
+    };
+    if let Some(fragment) = fragment {
+        stripped.push('#');
+        stripped.push_str(fragment);
+    }
+    stripped
+}
+
+// How the authentication ticket reaches the client. As an argument works with every client build but any local
+// process can read it from there, the other two need a client build that looks for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketPassing {
+    Argument,
+    Environment,
+    File,
+}
+
+pub const TICKET_ENVIRONMENT_VARIABLE: &str = "SYNTAX_AUTHENTICATION_TICKET";
+
+// The client reads the ticket file right after starting, it is removed once this is over
+const TICKET_FILE_LIFETIME: Duration = Duration::from_secs(15);
+
+impl TicketPassing {
+    pub fn from_name( name: &str ) -> Option<TicketPassing> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "argument" | "argv" => Some(TicketPassing::Argument),
+            "environment" | "env" => Some(TicketPassing::Environment),
+            "file" => Some(TicketPassing::File),
+            _ => None,
+        }
+    }
+}
+
+pub struct TicketHandoff {
+    file: Option<PathBuf>,
+    created: Instant,
+}
+
+impl TicketHandoff {
+    // Waits out the lifetime of the ticket file and removes it
+    pub fn finish( self ) {
+        if let Some(file) = self.file {
+            std::thread::sleep(TICKET_FILE_LIFETIME.saturating_sub(self.created.elapsed()));
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+// Adds the ticket to the client command. A ticket file is written next to the client and only readable by us,
+// the client runs from that directory so it gets a relative path that works the same under wine
+fn hand_off_ticket( command: &mut Command, ticket: &str, passing: TicketPassing, client_directory: &Path ) -> Result<TicketHandoff, std::io::Error> {
+    let mut file = None;
+    match passing {
+        TicketPassing::Argument => {
+            command.args(["--authenticationTicket", ticket]);
+        },
+        TicketPassing::Environment => {
+            command.env(TICKET_ENVIRONMENT_VARIABLE, ticket);
+        },
+        TicketPassing::File => {
+            let filename = format!("ticket-{}", std::process::id());
+            let path = client_directory.join(&filename);
+            write_private_file(&path, ticket)?;
+            command.current_dir(client_directory).args(["--authenticationTicketFile", filename.as_str()]);
+            file = Some(path);
+        }
+    }
+    Ok(TicketHandoff { file, created: Instant::now() })
+}
+
+fn write_private_file( path: &Path, contents: &str ) -> Result<(), std::io::Error> {
+    use std::io::Write;
+    let mut options = std::fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+// Serializes back into a uri that parses to the same request
+impl std::fmt::Display for LaunchRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", SCHEME, PROTOCOL_VERSION)?;
+        let known = [
+            ("launchmode", Some(&self.launch_mode)),
+            ("gameinfo", self.ticket.as_ref()),
+            ("placelauncherurl", self.join_script_url.as_ref()),
+            ("clientyear", self.client_year.as_ref()),
+        ];
+        for (key, value) in known {
+            if let Some(value) = value {
+                write!(f, "+{}:{}", key, percent_encode(value))?;
+            }
+        }
+        for (key, value) in &self.extra {
+            write!(f, "+{}:{}", percent_encode(key), percent_encode(value))?;
+        }
+        Ok(())
+    }
+}
+
+fn strip_scheme( uri: &str ) -> Option<&str> {
+    let (scheme, rest) = uri.split_once(':')?;
+    if !scheme.eq_ignore_ascii_case(SCHEME) {
+        return None;
+    }
+    Some(rest.strip_prefix("//").unwrap_or(rest))
+}
+
+// A url that still has its scheme written out is not encoded as a whole, decoding it would turn the escapes in its
+// query into the characters they stand for
+fn decode_value( value: &str ) -> Option<String> {
+    let scheme = value.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https") => Some(value.to_string()),
+        _ => percent_decode(value),
+    }
+}
+
+fn percent_decode( value: &str ) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// Replaces `value` in `text` however it is written there: as is, with any of its bytes percent-encoded in upper or
+// lower case hex, or encoded twice ( %2541 ) the way a url inside a uri ends up
+pub fn redact_encoded( text: &str, value: &str, replacement: &str ) -> String {
+    if value.is_empty() {
+        return text.to_string();
+    }
+    let bytes = text.as_bytes();
+    let mut redacted = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        match encoded_length(&bytes[index..], value.as_bytes()) {
+            // Matches start on a char boundary, value starts with a whole char and % is ascii
+            Some(length) => {
+                redacted.push_str(&text[copied..index]);
+                redacted.push_str(replacement);
+                index += length;
+                copied = index;
+            },
+            None => index += 1,
+        }
+    }
+    redacted.push_str(&text[copied..]);
+    redacted
+}
+
+// How many bytes at the start of `bytes` spell out `value`
+fn encoded_length( bytes: &[u8], value: &[u8] ) -> Option<usize> {
+    let mut length = 0;
+    for byte in value {
+        length += match bytes.get(length..)? {
+            [first, ..] if first == byte => 1,
+            [b'%', b'2', b'5', high, low, ..] if hex_byte(*high, *low) == Some(*byte) => 5,
+            [b'%', high, low, ..] if hex_byte(*high, *low) == Some(*byte) => 3,
+            _ => return None,
+        };
+    }
+    Some(length)
+}
+
+fn hex_byte( high: u8, low: u8 ) -> Option<u8> {
+    let high = (high as char).to_digit(16)?;
+    let low = (low as char).to_digit(16)?;
+    Some((high * 16 + low) as u8)
+}
+
+// Everything but unreserved characters is encoded, which keeps the + and : separators unambiguous
+fn percent_encode( value: &str ) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAY_URI: &str = "syntax-player://1+launchmode:play+gameinfo:TICKET+placelauncherurl:https%3A%2F%2Fwww.syntax.eco%2FGame%2Fplacelauncher.ashx%3FplaceId%3D660%26t%3DTICKET+k:l";
+
+    #[test]
+    fn parses_a_play_uri() {
+        let request = LaunchRequest::parse(PLAY_URI).unwrap();
+        assert_eq!(request.launch_mode, "play");
+        assert_eq!(request.ticket.as_deref(), Some("TICKET"));
+        assert_eq!(request.join_script_url.as_deref(), Some("https://www.syntax.eco/Game/placelauncher.ashx?placeId=660&t=TICKET"));
+        assert_eq!(request.client_year, None);
+        assert_eq!(request.extra, vec![("k".to_string(), "l".to_string())]);
+    }
+
+    #[test]
+    fn keeps_an_unencoded_script_url_verbatim() {
+        let request = LaunchRequest::parse("syntax-player://1+launchmode:play+gameinfo:TICKET+placelauncherurl:https://www.syntax.eco/Game/placelauncher.ashx?placeId=660&t=abc%2Bdef").unwrap();
+        assert_eq!(request.join_script_url.as_deref(), Some("https://www.syntax.eco/Game/placelauncher.ashx?placeId=660&t=abc%2Bdef"));
+        assert_eq!(LaunchRequest::parse(&request.to_string()).unwrap(), request);
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let request = LaunchRequest::parse(PLAY_URI).unwrap();
+        assert_eq!(LaunchRequest::parse(&request.to_string()).unwrap(), request);
+        let request = LaunchRequest::parse("SYNTAX-PLAYER:1+launchmode:playtest+placelauncherurl:https%3A%2F%2Fwww.syntax.eco%2Fa%20b+clientyear:2018/").unwrap();
+        assert_eq!(request.join_script_url.as_deref(), Some("https://www.syntax.eco/a b"));
+        assert_eq!(LaunchRequest::parse(&request.to_string()).unwrap(), request);
+    }
+
+    #[test]
+    fn rejects_malformed_uris() {
+        let error = |uri: &str| LaunchRequest::parse(uri).unwrap_err().to_string();
+        assert_eq!(error("https://www.syntax.eco"), LaunchRequestError::InvalidScheme.to_string());
+        assert_eq!(error("syntax-player://"), LaunchRequestError::MissingVersion.to_string());
+        assert_eq!(error("syntax-player://2+launchmode:play"), LaunchRequestError::UnsupportedVersion("2".to_string()).to_string());
+        assert_eq!(error("syntax-player://1+launchmode"), LaunchRequestError::MalformedField("launchmode".to_string()).to_string());
+        assert_eq!(error("syntax-player://1+launchmode:%ZZ"), LaunchRequestError::InvalidEncoding("launchmode".to_string()).to_string());
+        assert_eq!(error("syntax-player://1+launchmode:fly"), LaunchRequestError::UnknownLaunchMode("fly".to_string()).to_string());
+    }
+
+    #[test]
+    fn rejects_duplicate_and_missing_keys() {
+        assert!(matches!(LaunchRequest::parse(&format!("{}+gameinfo:OTHER", PLAY_URI)), Err(LaunchRequestError::DuplicateKey(key)) if key == "gameinfo"));
+        assert!(matches!(LaunchRequest::parse(&format!("{}+k:m", PLAY_URI)), Err(LaunchRequestError::DuplicateKey(key)) if key == "k"));
+        assert!(matches!(LaunchRequest::parse("syntax-player://1+gameinfo:TICKET"), Err(LaunchRequestError::MissingKey("launchmode"))));
+        assert!(matches!(LaunchRequest::parse("syntax-player://1+launchmode:play+placelauncherurl:x"), Err(LaunchRequestError::MissingKey("gameinfo"))));
+        assert!(matches!(LaunchRequest::parse("syntax-player://1+launchmode:edit+gameinfo:TICKET"), Err(LaunchRequestError::MissingKey("placelauncherurl"))));
+    }
+
+    #[test]
+    fn redacts_the_ticket_however_it_is_encoded() {
+        for text in ["a/b", "a%2Fb", "a%2fb", "%61%2F%62", "a%252Fb"] {
+            assert_eq!(redact_encoded(&format!("x {} y", text), "a/b", "[REDACTED]"), "x [REDACTED] y", "{}", text);
+        }
+        assert_eq!(redact_encoded("a%2Fc", "a/b", "[REDACTED]"), "a%2Fc");
+        let request = LaunchRequest::parse(PLAY_URI).unwrap();
+        let redacted = request.secrets().iter().fold(PLAY_URI.to_string(), |text, secret| redact_encoded(&text, secret, "[REDACTED]"));
+        assert!(!redacted.contains("TICKET"));
+    }
+
+    #[test]
+    fn takes_the_ticket_out_of_the_script_url() {
+        assert_eq!(without_ticket("https://www.syntax.eco/Game/placelauncher.ashx?placeId=660&t=TICKET", "TICKET"), "https://www.syntax.eco/Game/placelauncher.ashx?placeId=660");
+        assert_eq!(without_ticket("https://www.syntax.eco/a?ticket=A%2FB#top", "A/B"), "https://www.syntax.eco/a#top");
+        assert_eq!(without_ticket("https://www.syntax.eco/a?placeId=1", "TICKET"), "https://www.syntax.eco/a?placeId=1");
+    }
+}
@@ -1,123 +1,158 @@
 use colored::*;
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::Mutex;
 use reqwest::Client;
-use dirs::data_local_dir;
-use futures_util::StreamExt;
-use md5;
-use zip_extract;
-
-
-#[cfg(target_os = "windows")]
-use std::os::windows::prelude::FileExt;
-#[cfg(target_os = "windows")]
-use winreg::enums::*;
-#[cfg(target_os = "windows")]
-use winreg::RegKey;
-#[cfg(not(target_os = "windows"))]
-use std::io::prelude::*;
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::fs::FileExt;
+
+mod allowlist;
+mod cache;
+mod cli;
+mod clients;
+mod commands;
+mod config;
+mod delta;
+mod download;
+mod extract;
+mod fetch;
+mod install;
+mod inventory;
+mod launch;
+mod manifest;
+mod mirrors;
+mod packs;
+mod protocol;
+mod retry;
+mod state;
+mod versions;
+
+use download::http_get;
+use cache::PackageCache;
+use mirrors::Mirrors;
+use retry::RetryPolicy;
+
+// Values that must never show up in the output, like authentication tickets
+static REDACTED_VALUES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn redact_in_logs( value : &str ) {
+    if !value.is_empty() {
+        REDACTED_VALUES.lock().unwrap().push(value.to_string());
+    }
+}
+
+fn redact( message : &str ) -> String {
+    let mut message = message.to_string();
+    for value in REDACTED_VALUES.lock().unwrap().iter() {
+        message = launch::redact_encoded(&message, value, "[REDACTED]");
+    }
+    message
+}
 
 fn info( message : &str ) {
     let time = chrono::Local::now().format("%H:%M:%S").to_string();
-    println!("[{}] [{}] {}", time.bold().blue(), "INFO".bold().green(), message);
+    println!("[{}] [{}] {}", time.bold().blue(), "INFO".bold().green(), redact(message));
 }
 
 fn error( message : &str ) {
     let time = chrono::Local::now().format("%H:%M:%S").to_string();
-    println!("[{}] [{}] {}", time.bold().blue(), "ERROR".bold().red(), message);
+    println!("[{}] [{}] {}", time.bold().blue(), "ERROR".bold().red(), redact(message));
 }
 
 #[cfg(debug_assertions)]
 fn debug( message : &str ) {
     let time = chrono::Local::now().format("%H:%M:%S").to_string();
-    println!("[{}] [{}] {}", time.bold().blue(), "DEBUG".bold().yellow(), message);
+    println!("[{}] [{}] {}", time.bold().blue(), "DEBUG".bold().yellow(), redact(message));
 }
 
 #[cfg(not(debug_assertions))]
-fn debug( message : &str ) {}
-
-pub async fn http_get( client: &Client ,url: &str ) -> Result<String, reqwest::Error> {
-    debug(&format!("{} {}", "GET".green(), url.bright_blue()));
-    let response = client.get(url).send().await;
-    if (response.is_err()) {
-        debug(&format!("Failed to fetch {}", url.bright_blue()));
-        return Err(response.err().unwrap());
-    }
-    let response_body = response.unwrap().text().await.unwrap();
-    Ok(response_body)
-}
+fn debug( _message : &str ) {}
 
-pub async fn download_file( client: &Client, url: &str, path: &PathBuf ) {
-    debug(&format!("{} {}", "GET".green(), url.bright_blue()));
-    let response = client.get(url).send().await.unwrap();
-    let content_length = response.content_length().unwrap();
-    debug(&format!("Content Length: {}", content_length));
-
-    let time = chrono::Local::now().format("%H:%M:%S").to_string();
-    let pg_bar_str = "                {spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
-    let progress_bar = indicatif::ProgressBar::new(content_length);
-    let progress_style = indicatif::ProgressStyle::default_bar()
-        .template(
-            format!("{}\n{}", 
-            format!(
-                "[{}] [{}] {}", 
-                time.bold().blue(), 
-                "INFO".bold().green(), 
-                &format!("Downloading {}", &url.bright_blue())
-            ),
-            pg_bar_str).as_str()
-        )
-        .unwrap().progress_chars("#>-");
-    progress_bar.set_style(progress_style);
-    progress_bar.set_message("Downloading File");
-
-    let file = std::fs::File::create(path).unwrap();
-    let mut downloaded: u64 = 0;
-    let mut stream = response.bytes_stream();
-
-    while let Some(item) = stream.next().await {
-        let chunk = item.or(Err(format!("Error while downloading file"))).unwrap();
-        #[cfg(target_os = "windows")]
-        {
-            file.seek_write(chunk.as_ref(), downloaded).unwrap();
-        }
-        #[cfg(not(target_os = "windows"))]
-        {
-            file.write_at(chunk.as_ref(), downloaded).unwrap();
-        }
-        let new = std::cmp::min(downloaded + (chunk.len() as u64), content_length);
-        downloaded = new;
-        progress_bar.set_position(new);
+pub async fn create_folder_if_not_exists( path: &Path ) {
+    if !path.exists() {
+        info(&format!("Creating folder {}", path.to_str().unwrap().bright_blue()));
+        std::fs::create_dir_all(path).unwrap();
     }
-    progress_bar.finish();
-    info(format!("Finished downloading {}", url.green()).as_str());
-}
-
-pub async fn download_file_prefix( client: &Client, url: &str, path_prefix : &PathBuf ) -> PathBuf {
-    let path = path_prefix.join(generate_md5(url).await);
-    download_file(client, url, &path).await;
-    return path;
 }
 
-pub async fn generate_md5( input : &str ) -> String {
-    let hashed_input = md5::compute(input.as_bytes());
-    return format!("{:x}", hashed_input);
+fn finish_command( result: Result<(), String> ) -> ! {
+    if let Err(e) = result {
+        error(&e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
 }
 
-pub async fn create_folder_if_not_exists( path: &PathBuf ) {
-    if !path.exists() {
-        info(&format!("Creating folder {}", path.to_str().unwrap().bright_blue()));
-        std::fs::create_dir_all(path).unwrap();
+// Puts back the missing or modified files of an installed version, exits when that fails
+async fn repair_installation( install_state: &state::InstallState, installed_manifest: &manifest::Manifest, package_fetcher: &fetch::PackageFetcher<'_>, version_directory: &Path, base_url: &str ) {
+    match inventory::repair(install_state, installed_manifest, package_fetcher, version_directory).await {
+        Ok(repaired) if repaired.is_empty() => info("Every installed file is intact"),
+        Ok(repaired) => {
+            for path in &repaired {
+                debug(&format!("Repaired {}", path.bright_blue()));
+            }
+            info(&format!("Repaired {} files", repaired.len().to_string().bright_blue()));
+        },
+        Err(e) => {
+            error(&format!("Failed to repair the client files: {}", e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+    }
+    if let Err(e) = install::write_app_settings(version_directory, base_url) {
+        error(&format!("Failed to write AppSettings.xml: {}", e));
     }
 }
 
-fn get_installation_directory() -> PathBuf {
-    return PathBuf::from(data_local_dir().unwrap().to_str().unwrap()).join("Syntax");
+// Downloads the bootstrapper of `version`, checked against the digest its manifest publishes for it
+async fn download_bootstrapper( http_client: &Client, mirrors: &Mirrors, retry_policy: &RetryPolicy, version: &str, bootstrapper_filename: &str, bootstrapper_path: &Path ) {
+    let manifest = match manifest::fetch_manifest(http_client, mirrors, version, retry_policy).await {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error(&format!("Failed to fetch the package manifest for {}: {}", version.bright_blue(), e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+    };
+    let published = manifest.published_file(bootstrapper_filename);
+    #[cfg(target_os = "macos")]
+    let downloaded = download::download_file_with_retry(http_client, &format!("https://{}/{}", "sdh.gay/SyntaxMacOS", bootstrapper_filename), bootstrapper_path, published.map(|file| file.size), retry_policy).await // cause i cant upload to setup.syntax.eco lmao
+        .and_then(|()| download::verify_published(bootstrapper_path, bootstrapper_filename, published));
+    #[cfg(not(target_os = "macos"))]
+    let downloaded = download::download_file_from_mirrors(http_client, mirrors, &format!("{}-{}", version, bootstrapper_filename), bootstrapper_path, published, retry_policy).await;
+    if let Err(e) = downloaded {
+        error(&format!("Failed to download the latest bootstrapper: {}", e));
+        std::thread::sleep(std::time::Duration::from_secs(10));
+        std::process::exit(0);
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    // The newer bootstrapper gets the arguments exactly as we got them
+    let args: Vec<String> = std::env::args().collect();
+    let (cli, setting_flags) = cli::parse(&args);
+    let config = match config::Config::load(&setting_flags) {
+        Ok(config) => config,
+        Err(e) => {
+            error(&format!("Failed to load the configuration: {}", e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let bootstrapper_filename :&str = "SyntaxPlayerLauncher.exe";
+    #[cfg(target_os = "linux")]
+    let bootstrapper_filename :&str = "SyntaxPlayerLinuxLauncher";
+    #[cfg(target_os = "macos")]
+    let bootstrapper_filename :&str = "SyntaxPlayerMacOSLauncher";
+
+    // Commands that only deal with what is on disk run without the startup text
+    let command = match cli.command {
+        Some(cli::CliCommand::Status) => finish_command(commands::status(&config)),
+        Some(cli::CliCommand::Config { action }) => finish_command(commands::config(&config, action)),
+        Some(cli::CliCommand::Cache { action }) => finish_command(commands::cache(&config, action)),
+        Some(cli::CliCommand::Versions { action }) => finish_command(commands::versions(&config, action, bootstrapper_filename)),
+        Some(cli::CliCommand::Uninstall { keep_cache }) => finish_command(commands::uninstall(&config, keep_cache)),
+        command => command,
+    };
 
     // Clear the terminal before printing the startup text
     #[cfg(target_os = "windows")]
@@ -131,22 +166,10 @@ async fn main() {
     }
     #[cfg(not(target_os = "windows"))]
     {
-        std::process::Command::new("clear").spawn().unwrap();
+        std::process::Command::new("clear").status().unwrap();
     }
 
-    let args: Vec<String> = std::env::args().collect();
     let base_url : &str = "www.syntax.eco";
-    let mut setup_url : &str = "setup.syntax.eco";
-    let fallback_setup_url : &str = "d2f3pa9j0u8v6f.cloudfront.net";
-    let mut bootstrapper_filename :&str = "SyntaxPlayerLauncher.exe";
-    #[cfg(target_os = "linux")]
-    {
-        bootstrapper_filename = "SyntaxPlayerLinuxLauncher";
-    }
-    #[cfg(target_os = "macos")]
-    {
-        bootstrapper_filename = "SyntaxPlayerMacOSLauncher";
-    }
     let build_date = include_str!(concat!(env!("OUT_DIR"), "/build_date.txt"));
     let startup_text = format!("
     .d8888b. Y88b   d88P  888b    888 88888888888     d8888 Y88b   d88P 
@@ -166,7 +189,7 @@ async fn main() {
         terminal_width = w;
     }
     if terminal_width < 80 {
-        print!("{}\n", format!("SYNTAX Bootstrapper | {} | Build Date: {} | Version: {}", base_url, build_date, env!("CARGO_PKG_VERSION")).to_string().magenta().cyan().italic().on_black()); // Fallback message
+        println!("{}", format!("SYNTAX Bootstrapper | {} | Build Date: {} | Version: {}", base_url, build_date, env!("CARGO_PKG_VERSION")).magenta().cyan().italic().on_black()); // Fallback message
     } else {
         let startup_text_lines = startup_text.lines().collect::<Vec<&str>>();
         //println!("{}", startup_text.bold().blue().on_black());
@@ -186,42 +209,38 @@ async fn main() {
     }
 
 
-    let http_client: Client = reqwest::Client::builder()
-        .no_gzip()
-        .build()
-        .unwrap();
-    debug(format!("Setup Server: {} | Base Server: {}", setup_url.bright_blue(), base_url.bright_blue()).as_str());
-    debug("Fetching latest client version from setup server");
-    let latest_client_version : String;
-    let latest_client_version_response = http_get(&http_client ,&format!("https://{}/version", setup_url)).await;
-    match latest_client_version_response {
-        Ok(latest_client_version_result) => {
-            debug(&format!("Latest Client Version: {}", latest_client_version_result.bright_blue()));
-            latest_client_version = latest_client_version_result;
-        },
-        Err(e) => {
-            error(&format!("Failed to fetch latest client version from setup server: [{}], attempting to fallback to {}", e.to_string().bright_red(), fallback_setup_url.bright_blue()));
-            let fallback_client_version_response = http_get(&http_client ,&format!("https://{}/version", fallback_setup_url)).await;
-            match fallback_client_version_response {
-                Ok(fallback_client_version_result) => {
-                    info(&format!("Successfully fetched latest client version from fallback setup server: {}", fallback_setup_url.bright_blue()));
-                    debug(&format!("Latest Client Version: {}", fallback_client_version_result.bright_blue()));
-                    latest_client_version = fallback_client_version_result;
-                    setup_url = fallback_setup_url;
-                },
-                Err(e) => {
-                    error(&format!("Failed to fetch latest client version from fallback setup server: {}, are you connected to the internet?", e));
-                    std::thread::sleep(std::time::Duration::from_secs(10));
-                    std::process::exit(0);
-                }
+    let mut http_client_builder = reqwest::Client::builder().no_gzip();
+    if let Some(proxy) = &config.proxy {
+        match reqwest::Proxy::all(proxy) {
+            Ok(proxy) => http_client_builder = http_client_builder.proxy(proxy),
+            Err(e) => {
+                error(&format!("Invalid proxy {}: {}", proxy.bright_blue(), e));
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                std::process::exit(0);
             }
         }
     }
-    // Wait for the latest client version to be fetched
+    let http_client: Client = http_client_builder.build().unwrap();
+    let retry_policy = RetryPolicy::default();
+    let mirrors = Mirrors::new(&config.mirrors);
+    debug(format!("Setup Servers: {} | Base Server: {}", mirrors.base_urls().join(", ").bright_blue(), base_url.bright_blue()).as_str());
+    debug("Fetching latest client version from setup server");
+    let latest_client_version_response = mirrors.run("version", None, |url| {
+        let http_client = &http_client;
+        let retry_policy = &retry_policy;
+        async move { http_get(http_client, &url, retry_policy).await }
+    }).await;
+    let latest_client_version : String = match latest_client_version_response {
+        Ok(latest_client_version_result) => latest_client_version_result,
+        Err(e) => {
+            error(&format!("Failed to fetch latest client version from every setup server: {}, are you connected to the internet?", e));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+    };
     info(&format!("Latest Client Version: {}", latest_client_version.cyan().underline()));
-    debug(&format!("Setup Server: {}", setup_url.cyan().underline()));
 
-    let installation_directory = get_installation_directory();
+    let installation_directory = config.installation_directory();
     debug(&format!("Installation Directory: {}", installation_directory.to_str().unwrap().bright_blue()));
     create_folder_if_not_exists(&installation_directory).await;
 
@@ -229,11 +248,29 @@ async fn main() {
     debug(&format!("Versions Directory: {}", versions_directory.to_str().unwrap().bright_blue()));
     create_folder_if_not_exists(&versions_directory).await;
 
+    // After a rollback we keep using the older version until the setup server moves past the one rolled back from,
+    // or until asked to update
+    if let Some(cli::CliCommand::Update) = command {
+        versions::Rollback::clear(&installation_directory);
+    }
+    let latest_client_version = versions::resolve_current_version(&installation_directory, &versions_directory, &latest_client_version);
+
     let temp_downloads_directory = installation_directory.join("Downloads");
     debug(&format!("Temp Downloads Directory: {}", temp_downloads_directory.to_str().unwrap().bright_blue()));
     create_folder_if_not_exists(&temp_downloads_directory).await;
 
-    let current_version_directory = versions_directory.join(format!("{}", latest_client_version));
+    // Everything that installs or repairs packages gets them through the same cache and fetcher
+    let package_cache = PackageCache::new(installation_directory.join("Cache"), config.cache_size_limit());
+    let package_fetcher = fetch::PackageFetcher {
+        client: &http_client,
+        mirrors: &mirrors,
+        retry_policy: &retry_policy,
+        cache: &package_cache,
+        downloads_directory: &temp_downloads_directory,
+        concurrency: config.download_concurrency,
+    };
+
+    let current_version_directory = versions_directory.join(&latest_client_version);
     debug(&format!("Current Version Directory: {}", current_version_directory.to_str().unwrap().bright_blue()));
     create_folder_if_not_exists(&current_version_directory).await;
 
@@ -280,18 +317,10 @@ async fn main() {
     let current_exe_path = std::env::current_exe().unwrap();
     // If the current exe path is not in the current version directory, then we need to run the latest bootstrapper ( download if needed )
     if !current_exe_path.starts_with(&current_version_directory) {
-        // Check if the latest bootstrapper is downloaded
-        if !latest_bootstrapper_path.exists() {
+        // Check if the latest bootstrapper is downloaded ( or only partially downloaded )
+        if !latest_bootstrapper_path.exists() || download::is_partial_download(&latest_bootstrapper_path) {
             info("Downloading the latest bootstrapper and restarting");
-            // Download the latest bootstrapper
-            #[cfg(target_os = "macos")]
-            {    
-                download_file(&http_client, &format!("https://{}/{}", "sdh.gay/SyntaxMacOS", bootstrapper_filename), &latest_bootstrapper_path).await; // cause i cant upload to setup.syntax.eco lmao
-            }
-            #[cfg(not(target_os = "macos"))]
-            {    
-                download_file(&http_client, &format!("https://{}/{}-{}", setup_url, latest_client_version, bootstrapper_filename), &latest_bootstrapper_path).await;
-            }
+            download_bootstrapper(&http_client, &mirrors, &retry_policy, &latest_client_version, bootstrapper_filename, &latest_bootstrapper_path).await;
         }
         // Run the latest bootstrapper ( with the same arguments passed to us ) and exit
         #[cfg(target_os = "windows")]
@@ -304,7 +333,7 @@ async fn main() {
                     debug(&format!("Bootstrapper errored with error {}", e));
                     info("Found bootstrapper was corrupted! Downloading...");
                     std::fs::remove_file(latest_bootstrapper_path.clone()).unwrap();
-                    download_file(&http_client, &format!("https://{}/{}-{}", setup_url, latest_client_version, bootstrapper_filename), &latest_bootstrapper_path).await;
+                    download_bootstrapper(&http_client, &mirrors, &retry_policy, &latest_client_version, bootstrapper_filename, &latest_bootstrapper_path).await;
                     command.spawn().expect("Bootstrapper is still corrupted.");
                     std::thread::sleep(std::time::Duration::from_secs(20));
                 }
@@ -313,233 +342,182 @@ async fn main() {
         #[cfg(target_os = "linux")]
         {
             // Make sure the latest bootstrapper is executable
-            std::process::Command::new("chmod").arg("+x").arg(latest_bootstrapper_path.to_str().unwrap()).spawn().unwrap();
+            std::process::Command::new("chmod").arg("+x").arg(latest_bootstrapper_path.to_str().unwrap()).status().unwrap();
 
             info("We need permission to run the latest bootstrapper");
             let mut command = std::process::Command::new(latest_bootstrapper_path);
-
+            command.args(&args[1..]);
+            command.status().unwrap();
         }
         #[cfg(target_os = "macos")]
         {
-            std::process::Command::new("chmod").arg("+x").arg(latest_bootstrapper_path.to_str().unwrap()).spawn().unwrap();
-            let mut child = std::process::Command::new("zsh").arg("-c").arg(&format!("'{}'", latest_bootstrapper_path.to_str().unwrap())).spawn().unwrap();
-            let _ = child.wait().unwrap(); // horrible fix to a horrible problem
+            std::process::Command::new("chmod").arg("+x").arg(latest_bootstrapper_path.to_str().unwrap()).status().unwrap();
+            let mut command = std::process::Command::new(latest_bootstrapper_path);
+            command.args(&args[1..]);
+            command.status().unwrap();
         }
         std::process::exit(0);
     }
 
     // Looks like we are running from the latest version directory, so we can continue with the update process
-    // Check for "AppSettings.xml" in the current version directory 
-    // If it doesent exist, then we got either a fresh directory or a corrupted installation
-    // So delete the every file in the current version directory except for the Bootstrapper itself
-    let app_settings_path = current_version_directory.join("AppSettings.xml");
-    let client_executable_path = current_version_directory.join("SyntaxPlayerBeta.exe");
-    if !app_settings_path.exists() || !client_executable_path.exists() {
+    // The state file is written last, a version directory with one for this version holds a complete install.
+    // Installs from before the state file are judged by their "AppSettings.xml" and client executable instead,
+    // if either is missing we got either a fresh directory or a corrupted installation
+    // Installs with a state file are repaired file by file, anything else is installed from scratch
+    // The client is installed into a staging directory first and only replaces the version directory once it is complete
+    let repair = matches!(command, Some(cli::CliCommand::Repair));
+    let install_state = state::InstallState::load(&current_version_directory);
+    let needs_install = match &install_state {
+        Some(install_state) => install_state.version != latest_client_version,
+        None => !current_version_directory.join("AppSettings.xml").exists() || !current_version_directory.join("SyntaxPlayerBeta.exe").exists(),
+    };
+    let repairable = install_state
+        .zip(manifest::Manifest::load(&current_version_directory.join(delta::INSTALLED_MANIFEST_FILENAME)));
+    if let (true, Some((install_state, installed_manifest))) = (repair || needs_install, &repairable) {
+        create_folder_if_not_exists(&temp_downloads_directory).await;
+        repair_installation(install_state, installed_manifest, &package_fetcher, &current_version_directory, base_url).await;
+        let _ = std::fs::remove_dir_all(&temp_downloads_directory);
+    } else if repair || needs_install {
+        if repair {
+            info("Reinstalling the current version, it was installed before repairs were possible");
+        }
         info("Downloading the latest client files, this may take a while.");
-        for entry in std::fs::read_dir(&current_version_directory).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if path.is_file() {
-                if path != current_exe_path {
-                    std::fs::remove_file(path).unwrap();
-                }
-            } else {
-                std::fs::remove_dir_all(path).unwrap();
-            }
+        let staging_directory = install::staging_directory(&versions_directory, &latest_client_version);
+        if let Err(e) = install::prepare_staging(&staging_directory) {
+            error(&format!("Failed to prepare {}: {}", staging_directory.to_str().unwrap().bright_blue(), e));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
         }
 
-        let VersionURLPrefix = format!("https://{}/{}-", setup_url, latest_client_version);
-        let SyntaxAppZip : PathBuf = download_file_prefix(&http_client, format!("{}SyntaxApp.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let NPSyntaxProxyZip : PathBuf = download_file_prefix(&http_client, format!("{}NPSyntaxProxy.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let SyntaxProxyZip : PathBuf = download_file_prefix(&http_client, format!("{}SyntaxProxy.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let LibrariesZip : PathBuf = download_file_prefix(&http_client, format!("{}Libraries.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let RedistZip : PathBuf = download_file_prefix(&http_client, format!("{}redist.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-
-        let ContentTexturesZip : PathBuf = download_file_prefix(&http_client, format!("{}content-textures.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentTextures2Zip : PathBuf = download_file_prefix(&http_client, format!("{}content-textures2.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentTextures3Zip : PathBuf = download_file_prefix(&http_client, format!("{}content-textures3.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentTerrainZip : PathBuf = download_file_prefix(&http_client, format!("{}content-terrain.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentFontsZip : PathBuf = download_file_prefix(&http_client, format!("{}content-fonts.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentSoundsZip : PathBuf = download_file_prefix(&http_client, format!("{}content-sounds.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentScriptsZip : PathBuf = download_file_prefix(&http_client, format!("{}content-scripts.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentSkyZip : PathBuf = download_file_prefix(&http_client, format!("{}content-sky.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentMusicZip : PathBuf = download_file_prefix(&http_client, format!("{}content-music.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let ContentParticles : PathBuf = download_file_prefix(&http_client, format!("{}content-particles.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-
-        let ShadersZip : PathBuf = download_file_prefix(&http_client, format!("{}shaders.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-
-        let Client2018Zip : PathBuf = download_file_prefix(&http_client, format!("{}2018client.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let Client2020Zip : PathBuf = download_file_prefix(&http_client, format!("{}2020client.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        let Client2014Zip : PathBuf = download_file_prefix(&http_client, format!("{}2014client.zip", VersionURLPrefix).as_str(), &temp_downloads_directory).await;
-        info("Download finished, extracting files.");
-
-        fn extract_to_dir( zip_file : &PathBuf, target_dir : &PathBuf ) {
-            let zip_file_cursor = std::fs::File::open(zip_file).unwrap();
-            zip_extract::extract(zip_file_cursor, target_dir, false).unwrap();
+        let manifest = match manifest::fetch_manifest(&http_client, &mirrors, &latest_client_version, &retry_policy).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error(&format!("Failed to fetch the package manifest for {}: {}", latest_client_version.bright_blue(), e.to_string().bright_red()));
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                std::process::exit(0);
+            }
+        };
+
+        if let Some(previous_manifest) = delta::find_previous_manifest(&versions_directory, &latest_client_version) {
+            let version_diff = delta::diff(&previous_manifest, &manifest);
+            info(&format!(
+                "Changes since {}: {} unchanged, {} changed, {} added, {} removed",
+                previous_manifest.version.bright_blue(),
+                version_diff.unchanged.len(), version_diff.changed.len(), version_diff.added.len(), version_diff.removed.len()
+            ));
+            debug(&format!("Changed packages: {}", version_diff.changed.join(", ")));
         }
-        extract_to_dir(&SyntaxAppZip, &current_version_directory);
-        extract_to_dir(&NPSyntaxProxyZip, &current_version_directory);
-        extract_to_dir(&SyntaxProxyZip, &current_version_directory);
-        extract_to_dir(&LibrariesZip, &current_version_directory);
-        extract_to_dir(&RedistZip, &current_version_directory);
-
-        let content_directory = current_version_directory.join("content");
-        let platform_content_directory = current_version_directory.join("PlatformContent");
-        let shaders_directory = current_version_directory.join("shaders");
-
-        create_folder_if_not_exists(&content_directory).await;
-        create_folder_if_not_exists(&platform_content_directory).await;
-        create_folder_if_not_exists(&shaders_directory).await;
-
-        let fonts_directory = content_directory.join("fonts");
-        let music_directory = content_directory.join("music");
-        let particles_directory = content_directory.join("particles");
-        let sky_directory = content_directory.join("sky");
-        let sounds_directory = content_directory.join("sounds");
-        let textures_directory = content_directory.join("textures");
-        let scripts_directory = content_directory.join("scripts");
-
-        create_folder_if_not_exists(&fonts_directory).await;
-        create_folder_if_not_exists(&music_directory).await;
-        create_folder_if_not_exists(&particles_directory).await;
-        create_folder_if_not_exists(&sky_directory).await;
-        create_folder_if_not_exists(&sounds_directory).await;
-        create_folder_if_not_exists(&textures_directory).await;
-
-        extract_to_dir(&ContentTexturesZip, &textures_directory);
-        extract_to_dir(&ContentTextures2Zip, &textures_directory);
-        extract_to_dir(&ContentFontsZip, &fonts_directory);
-        extract_to_dir(&ContentSoundsZip, &sounds_directory);
-        extract_to_dir(&ContentSkyZip, &sky_directory);
-        extract_to_dir(&ContentMusicZip, &music_directory);
-        extract_to_dir(&ContentParticles, &particles_directory);
-        extract_to_dir(&ContentScriptsZip, &scripts_directory);
-
-        let platform_pc_directory = platform_content_directory.join("pc");
-        create_folder_if_not_exists(&platform_pc_directory).await;
-        let terrain_directory = platform_pc_directory.join("terrain");
-        let textures_directory = platform_pc_directory.join("textures");
-        create_folder_if_not_exists(&terrain_directory).await;
-        create_folder_if_not_exists(&textures_directory).await;
-
-        extract_to_dir(&ContentTerrainZip, &terrain_directory);
-        extract_to_dir(&ContentTextures3Zip, &textures_directory);
-        extract_to_dir(&ShadersZip, &shaders_directory);
-
-        let client_2018_directory = current_version_directory.join("Client2018");
-        create_folder_if_not_exists(&client_2018_directory).await;
-        extract_to_dir(&Client2018Zip, &client_2018_directory);
-
-        let client_2020_directory = current_version_directory.join("Client2020");
-        create_folder_if_not_exists(&client_2020_directory).await;
-        extract_to_dir(&Client2020Zip, &client_2020_directory);
-
-        let client_2014_directory = current_version_directory.join("Client2014");
-        create_folder_if_not_exists(&client_2014_directory).await;
-        extract_to_dir(&Client2014Zip, &client_2014_directory);
-
-        info("Finished extracting files, cleaning up.");
-        std::fs::remove_dir_all(&temp_downloads_directory).unwrap();
 
-        // Install the syntax-player scheme in the registry
-        info("Installing syntax-player scheme");
-        #[cfg(target_os = "windows")]
-        {
-            let hkey_current_user = RegKey::predef(HKEY_CURRENT_USER);
-            let hkey_classes_root : RegKey = hkey_current_user.open_subkey("Software\\Classes").unwrap();
-            let hkey_syntax_player = hkey_classes_root.create_subkey("syntax-player").unwrap().0;
-            let hkey_syntax_player_shell = hkey_syntax_player.create_subkey("shell").unwrap().0;
-            let hkey_syntax_player_shell_open = hkey_syntax_player_shell.create_subkey("open").unwrap().0;
-            let hkey_syntax_player_shell_open_command = hkey_syntax_player_shell_open.create_subkey("command").unwrap().0;
-            let defaulticon = hkey_syntax_player.create_subkey("DefaultIcon").unwrap().0;
-            hkey_syntax_player_shell_open_command.set_value("", &format!("\"{}\" \"%1\"", current_exe_path.to_str().unwrap())).unwrap();
-            defaulticon.set_value("", &format!("\"{}\",0", current_exe_path.to_str().unwrap())).unwrap();
-            hkey_syntax_player.set_value("", &format!("URL: Syntax Protocol")).unwrap();
-            hkey_syntax_player.set_value("URL Protocol", &"").unwrap();
+        // Year clients are installed the first time a launch asks for them
+        let client_registry = clients::ClientRegistry::builtin().with_overrides(&manifest.clients);
+        for client_variant in client_registry.missing_from(&manifest) {
+            info(&format!("{} does not list {}, the {} client will not be available", latest_client_version.bright_blue(), client_variant.package.bright_blue(), client_variant.year.bright_blue()));
         }
-        #[cfg(target_os = "linux")]
-        {
-            // Linux support
-            // We have to write a .desktop file to ~/.local/share/applications
-            let desktop_file_path = dirs::data_local_dir().unwrap().join("applications").join("syntax-player.desktop");
-            let desktop_file = format!(
-"[Desktop Entry]
-Name=Syntax Launcher
-Exec={} %u
-Terminal=true
-Type=Application
-MimeType=x-scheme-handler/syntax-player;
-Icon={}
-StartupWMClass=SyntaxLauncher
-Categories=Game;
-Comment=Syntax Launcher
-", current_exe_path.to_str().unwrap(), current_exe_path.to_str().unwrap());
-            std::fs::write(desktop_file_path, desktop_file).unwrap();
-            // We also have to write a mimeapps.list file to ~/.config
-            let mimeapps_list_path = dirs::config_dir().unwrap().join("mimeapps.list");
-            let mimeapps_list = format!(
-"[Default Applications]
-x-scheme-handler/syntax-player=syntax-player.desktop
-");
-            std::fs::write(mimeapps_list_path, mimeapps_list).unwrap();
-            // We also have to write a mimeapps.list file to ~/.local/share
-            let mimeapps_list_path = dirs::data_local_dir().unwrap().join("mimeapps.list");
-            let mimeapps_list = format!(
-"[Default Applications]
-x-scheme-handler/syntax-player=syntax-player.desktop
-");
-            std::fs::write(mimeapps_list_path, mimeapps_list).unwrap();
+        // Optional packs follow the previous install unless told otherwise
+        let excluded_packs = config.without_packs.clone()
+            .or_else(|| versions::installed_versions(&versions_directory).first().and_then(|installed| packs::PackRecord::load(&installed.path)).map(|record| record.excluded))
+            .unwrap_or_default();
+        if !excluded_packs.is_empty() {
+            info(&format!("Leaving out optional packs: {}", excluded_packs.join(", ").bright_blue()));
+        }
+        let base_manifest = manifest.subset(|package| !client_registry.is_client_package(&package.name) && packs::is_selected(package, &excluded_packs));
+        if base_manifest.packages.len() < manifest.packages.len() {
+            debug(&format!("Leaving {} packages out of the base install", manifest.packages.len() - base_manifest.packages.len()));
         }
 
-        // Write the AppSettings.xml file
-        let app_settings_xml = format!(
-"<?xml version=\"1.0\" encoding=\"UTF-8\"?>
-<Settings>
-	<ContentFolder>content</ContentFolder>
-	<BaseUrl>https://{}</BaseUrl>
-</Settings>", base_url
-        );
-        std::fs::write(app_settings_path, app_settings_xml).unwrap();
-
-        // Check for any other version directories and deletes them
-        for entry in std::fs::read_dir(&versions_directory).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if path.is_dir() {
-                if path != current_version_directory {
-                    std::fs::remove_dir_all(path).unwrap();
-                }
+        // Packages are extracted as their downloads finish, and leave the cache once extracted if it is over its limit
+        debug(&format!("Fetching {} packages, {} at a time", base_manifest.packages.len(), package_fetcher.concurrency));
+        let package_files = match install::fetch_and_extract(&package_fetcher, &base_manifest, &staging_directory).await {
+            Ok(package_files) => package_files,
+            Err(e) => {
+                error(&format!("Failed to install the client files: {}", e.to_string().bright_red()));
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                std::process::exit(0);
             }
+        };
+        manifest.save(&staging_directory.join(delta::INSTALLED_MANIFEST_FILENAME)).unwrap();
+        install::write_app_settings(&staging_directory, base_url).unwrap();
+        let installed_packages = base_manifest.packages.iter().zip(package_files).collect::<Vec<(&manifest::Package, Vec<inventory::InstalledFile>)>>();
+        state::InstallState::for_install(&manifest, &excluded_packs, installed_packages, &staging_directory).save(&staging_directory).unwrap();
+
+        let install_result = install::verify_staging(&staging_directory, &base_manifest.packages, &["AppSettings.xml", "SyntaxPlayerBeta.exe", state::STATE_FILENAME])
+            .and_then(|_| install::commit_staging(&staging_directory, &current_version_directory, &current_exe_path));
+        if let Err(e) = install_result {
+            error(&format!("Failed to install the client files: {}", e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+
+        info("Finished extracting files, cleaning up.");
+        std::fs::remove_dir_all(&temp_downloads_directory).unwrap();
+
+        match versions::apply_retention(&versions_directory, &latest_client_version, config.keep_versions) {
+            Ok(()) => {},
+            Err(e) => error(&format!("Failed to remove old versions: {}", e)),
         }
         #[cfg(target_os = "macos")]
         {
-            // this is the worst way to do it but its my last resort cause idk what else to do...... sorry!!!!
-            let script = format!(
-"import os, sys
-version = \"{}\"
-try:
-    sys.argv[1]
-    os.system(\"osascript -e \'tell application \\\"Terminal\\\"\' -e \'do script \\\"unset HISTFILE && ~/Library/Application\\\\\\ Support/Syntax/Versions/\" + version + \"/SyntaxPlayerMacOSLauncher \\\\\\\"\" + sys.argv[1] + \"\\\\\\\" && exit\\\"\' -e \'activate application \\\"Terminal\\\"\' -e \'end tell\'\")
-except IndexError:
-    os.system(\"osascript -e \'tell application \\\"Terminal\\\"\' -e \'do script \\\"unset HISTFILE && ~/Library/Application\\\\\\ Support/Syntax/Versions/\" + version + \"/SyntaxPlayerMacOSLauncher\"\" && exit\\\"\' -e \'activate application \\\"Terminal\\\"\' -e \'end tell\'\")",latest_client_version
-            );
             info("Downloading & Extracting Syntax Player");
             create_folder_if_not_exists(&temp_downloads_directory).await;
-            let SyntaxMac = download_file_prefix(&http_client, format!("{}SyntaxPlayer.zip", "https://sdh.gay/SyntaxMacOS/").as_str(), &temp_downloads_directory).await; // replace the discord url with a better server later (e.g github pages idk)
+            let syntax_mac = temp_downloads_directory.join("SyntaxPlayer.zip");
+            // replace the discord url with a better server later (e.g github pages idk)
+            let published = manifest.published_file("SyntaxPlayer.zip");
+            let downloaded = download::download_file_with_retry(&http_client, "https://sdh.gay/SyntaxMacOS/SyntaxPlayer.zip", &syntax_mac, published.map(|file| file.size), &retry_policy).await
+                .and_then(|()| download::verify_published(&syntax_mac, "SyntaxPlayer.zip", published));
+            if let Err(e) = downloaded {
+                error(&format!("Failed to download Syntax Player: {}", e.to_string().bright_red()));
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                std::process::exit(0);
+            }
             debug("download finished");
-            extract_to_dir(&SyntaxMac, &PathBuf::from("/Applications/"));
+            if let Err(e) = install::extract_to_dir(&syntax_mac, extract::ArchiveFormat::Zip, Path::new("/Applications/")) {
+                error(&format!("Failed to extract Syntax Player: {}", e.to_string().bright_red()));
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                std::process::exit(0);
+            }
             std::fs::remove_dir_all(&temp_downloads_directory).unwrap();
-            std::fs::write("/Applications/Syntax Player.app/Contents/Resources/script", script).unwrap();
-            std::process::Command::new("chmod").arg("+x").arg("/Applications/Syntax Player.app/Contents/Resources/script").spawn().unwrap();
-            std::process::Command::new("duti").arg("-s").arg("Syn.tax.Player").arg("syntax-player");
+        }
 
+        // Install the syntax-player scheme
+        protocol::register(&current_exe_path);
+    }
+
+    // The installed manifest knows the optional packs and client years of this version
+    let installed_manifest = manifest::Manifest::load(&current_version_directory.join(delta::INSTALLED_MANIFEST_FILENAME));
+
+    // Add or remove optional packs when the selection changed since this version was installed
+    if let (Some(excluded_packs), Some(installed_manifest)) = (config.without_packs.clone(), &installed_manifest) {
+        // Installs from before packs were recorded have every pack
+        let pack_record = packs::PackRecord::load(&current_version_directory).unwrap_or_else(|| packs::PackRecord::for_manifest(installed_manifest, &[]));
+        let mut requested = excluded_packs.clone();
+        let mut recorded = pack_record.excluded.clone();
+        requested.sort();
+        recorded.sort();
+        if requested != recorded {
+            create_folder_if_not_exists(&temp_downloads_directory).await;
+            match packs::change_selection(installed_manifest, &pack_record, &excluded_packs, &package_fetcher, &current_version_directory).await {
+                Ok(()) => {},
+                Err(e) => {
+                    error(&format!("Failed to change the optional packs: {}", e.to_string().bright_red()));
+                    std::thread::sleep(std::time::Duration::from_secs(10));
+                    std::process::exit(0);
+                }
+            }
+            let _ = std::fs::remove_dir_all(&temp_downloads_directory);
         }
     }
 
-    // Parse the arguments passed to the bootstrapper
+    // Parse the uri passed to the bootstrapper
     // Looks something like "syntax-player://1+launchmode:play+gameinfo:TICKET+placelauncherurl:https://www.syntax.eco/Game/placelauncher.ashx?placeId=660&t=TICKET+k:l"
-    debug(&format!("Arguments Passed: {}", args.join(" ").bright_blue()));
-    if args.len() == 1 {
+    let launch_uri = match command {
+        Some(cli::CliCommand::Launch { uri }) => Some(uri),
+        Some(_) => {
+            info(&format!("Version {} is installed", latest_client_version.cyan().underline()));
+            std::process::exit(0);
+        },
+        None => None,
+    };
+    let Some(launch_uri) = launch_uri else {
         // Just open the website
         #[cfg(target_os = "windows")]
         {
@@ -556,104 +534,144 @@ except IndexError:
             std::process::Command::new("open").arg("https://www.syntax.eco/games").spawn().unwrap();
             std::process::exit(0);
         }
-    }
-
-    let main_args = &args[1];
-    let main_args = main_args.replace("syntax-player://", "");
-    let main_args = main_args.split("+").collect::<Vec<&str>>();
+    };
 
-    let mut launch_mode = String::new();
-    let mut authentication_ticket = String::new();
-    let mut join_script = String::new();
-    let mut client_year = String::new();
-    
-    for arg in main_args {
-        let mut arg_split = arg.split(":");
-        let key = arg_split.next().unwrap();
-        let value =
-            if arg_split.clone().count() > 0 {
-                arg_split.collect::<Vec<&str>>().join(":")
-            } else {
-                String::new()
-            };
-        debug(&format!("{}: {}", key.bright_blue(), value.bright_blue()));
-        match key {
-            "launchmode" => {
-                launch_mode = value.to_string();
-            },
-            "gameinfo" => {
-                authentication_ticket = value.to_string();
-            },
-            "placelauncherurl" => {
-                join_script = value.to_string();
-            },
-            "clientyear" => {
-                client_year = value.to_string();
-            },
-            _ => {}
+    let launch_request = match launch::LaunchRequest::parse(&launch_uri) {
+        Ok(launch_request) => launch_request,
+        Err(e) => {
+            error(&format!("Invalid launch uri: {}", e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+    };
+    // The ticket is in both gameinfo and the place launcher url, percent-encoded in different ways
+    for secret in launch_request.secrets() {
+        redact_in_logs(&secret);
+    }
+    debug(&format!("Arguments Passed: {}", args.join(" ").bright_blue()));
+    debug(&format!("Launch Mode: {} | Client Year: {}", launch_request.launch_mode.bright_blue(), launch_request.client_year.as_deref().unwrap_or("default").bright_blue()));
+    for (key, value) in &launch_request.extra {
+        debug(&format!("Ignoring unknown launch parameter {}: {}", key.bright_blue(), value.bright_blue()));
+    }
+    // The client fetches whatever the join script url points at, so only our own hosts are accepted
+    let url_allowlist = allowlist::UrlAllowlist::new(base_url, &config.allowed_hosts);
+    if let Some(join_script_url) = &launch_request.join_script_url {
+        if let Err(e) = url_allowlist.check(join_script_url) {
+            error(&format!("Refusing to launch, the place launcher url is not trusted: {}", e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
         }
     }
+    let launch_mode = launch::find_launch_mode(&launch_request.launch_mode).unwrap();
+    let client_year = launch_request.client_year.clone().unwrap_or_default();
 
-    let custom_wine = "wine64";
     #[cfg(not(target_os = "windows"))]
     {
-        // We allow user to specify the wine binary path in installation_directory/winepath.txt
-        let wine_path_file = installation_directory.join("winepath.txt");
-        if wine_path_file.exists() {
-            let custom_wine = std::fs::read_to_string(wine_path_file).unwrap();
-            info(&format!("Using custom wine binary: {}", custom_wine.bright_blue()));
-        } else {
-            info("No custom wine binary specified, using default wine command");
-            info(format!("If you want to use a custom wine binary, please create a file at {} with the path to the wine binary", wine_path_file.to_str().unwrap()).as_str());
+        match &config.wine_path {
+            Some(wine_path) => info(&format!("Using custom wine binary: {}", wine_path.bright_blue())),
+            None => {
+                info("No custom wine binary specified, using default wine command");
+                info(&format!("If you want to use a custom wine binary, set wine_path in {}", config::config_path().to_str().unwrap()));
+            }
         }
     }
-    let client_executable_path : PathBuf;
-    debug(&client_year.to_string());
-    if client_year == "2018" {
-        client_executable_path = current_version_directory.join("Client2018").join("SyntaxPlayerBeta.exe");
-    } else if client_year == "2020" {
-        client_executable_path = current_version_directory.join("Client2020").join("SyntaxPlayerBeta.exe");
-    } else if client_year == "2014" {
-        client_executable_path = current_version_directory.join("Client2014").join("SyntaxPlayerBeta.exe");
-    } else {
-        client_executable_path = current_version_directory.join("SyntaxPlayerBeta.exe");
+    // Client years come from the built in registry and whatever the installed manifest adds to it
+    let client_registry = clients::ClientRegistry::builtin().with_overrides(installed_manifest.as_ref().map(|manifest| manifest.clients.as_slice()).unwrap_or_default());
+    let client_variant = match client_year.as_str() {
+        "" => None,
+        year => {
+            let client_variant = client_registry.find(year);
+            if client_variant.is_none() {
+                info(&format!("Unknown client year {}, using the default client", year.bright_blue()));
+            }
+            client_variant
+        }
+    };
+    let client_executable_path = match client_variant {
+        Some(client_variant) => client_variant.executable_path(&current_version_directory, launch_mode.executable),
+        None => current_version_directory.join(launch_mode.executable),
+    };
+    debug(&format!("Client Executable: {}", client_executable_path.to_str().unwrap().bright_blue()));
+    if let (Some(client_variant), false) = (client_variant, client_executable_path.exists()) {
+        let installed_manifest = match &installed_manifest {
+            Some(installed_manifest) => installed_manifest,
+            None => {
+                error(&format!("Can not install the {} client, {} has no manifest. Delete {} to reinstall.", client_variant.year.bright_blue(), latest_client_version.bright_blue(), current_version_directory.to_str().unwrap().bright_blue()));
+                std::thread::sleep(std::time::Duration::from_secs(10));
+                std::process::exit(0);
+            }
+        };
+        create_folder_if_not_exists(&temp_downloads_directory).await;
+        if let Err(e) = clients::install_client_variant(client_variant, installed_manifest, &package_fetcher, &current_version_directory).await {
+            error(&format!("Failed to install the {} client: {}", client_variant.year.bright_blue(), e.to_string().bright_red()));
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            std::process::exit(0);
+        }
+        let _ = std::fs::remove_dir_all(&temp_downloads_directory);
+    }
+    // Only a missing executable the install put there means the install is broken, installs from before the state file
+    // always came with SyntaxPlayerBeta.exe
+    let install_state = state::InstallState::load(&current_version_directory);
+    let executable_relative_path = inventory::inventory_path(client_executable_path.strip_prefix(&current_version_directory).unwrap_or(&client_executable_path));
+    let executable_installed = match &install_state {
+        Some(install_state) => install_state.records(&executable_relative_path),
+        None => executable_relative_path == "SyntaxPlayerBeta.exe",
+    };
+    if !client_executable_path.exists() && !executable_installed {
+        error(&format!("Version {} has no {}, {} can not be launched", latest_client_version.bright_blue(), executable_relative_path.bright_blue(), launch_mode.description));
+        std::thread::sleep(std::time::Duration::from_secs(20));
+        std::process::exit(0);
+    }
+    if let (false, Some(install_state), Some(installed_manifest)) = (client_executable_path.exists(), &install_state, &installed_manifest) {
+        info(&format!("{} is missing, repairing the install", client_executable_path.file_name().unwrap().to_string_lossy().bright_blue()));
+        create_folder_if_not_exists(&temp_downloads_directory).await;
+        repair_installation(install_state, installed_manifest, &package_fetcher, &current_version_directory, base_url).await;
+        let _ = std::fs::remove_dir_all(&temp_downloads_directory);
     }
     if !client_executable_path.exists() {
-        // Delete AppSettings.xml so the bootstrapper will download the client again
-        let app_settings_path = current_version_directory.join("AppSettings.xml");
-        std::fs::remove_file(app_settings_path).unwrap();
+        // Delete the state file and AppSettings.xml so the bootstrapper will download the client again
+        let _ = std::fs::remove_file(current_version_directory.join(state::STATE_FILENAME));
+        let _ = std::fs::remove_file(current_version_directory.join("AppSettings.xml"));
 
-        error("Failed to run SyntaxPlayerBeta.exe, is your antivirus removing it? The bootstrapper will attempt to redownload the client on next launch.");
+        error(&format!("Failed to run {}, is your antivirus removing it? The bootstrapper will attempt to redownload the client on next launch.", client_executable_path.file_name().unwrap().to_string_lossy()));
         std::thread::sleep(std::time::Duration::from_secs(20));
         std::process::exit(0);
     }
-    match launch_mode.as_str() {
-        "play" => {
-            info("Launching SYNTAX");
-            #[cfg(target_os = "windows")]
-            {           
-                let mut command = std::process::Command::new(client_executable_path);
-                command.args(&["--play","--authenticationUrl", format!("https://{}/Login/Negotiate.ashx", base_url).as_str(), "--authenticationTicket", authentication_ticket.as_str(), "--joinScriptUrl", format!("{}",join_script.as_str()).as_str()]);
-                command.spawn().unwrap();
-                std::thread::sleep(std::time::Duration::from_secs(5));
-                std::process::exit(0);
-            }
-            #[cfg(not(target_os = "windows"))]
-            {
-                // We have to launch the game through wine
-                let mut command = std::process::Command::new(custom_wine);
-                command.args(&[client_executable_path.to_str().unwrap(), "--play","--authenticationUrl", format!("https://{}/Login/Negotiate.ashx", base_url).as_str(), "--authenticationTicket", authentication_ticket.as_str(), "--joinScriptUrl", format!("{}",join_script.as_str()).as_str()]);
-                // We must wait for the game to exit before exiting the bootstrapper
-                let mut child = command.spawn().unwrap();
-                child.wait().unwrap();
-                std::thread::sleep(std::time::Duration::from_secs(1));
-                std::process::exit(0);
-            }
-        },
-        _ => {
-            error("Unknown launch mode, exiting.");
+    let ticket_passing = config.ticket_passing;
+    let authentication_url = format!("https://{}/Login/Negotiate.ashx", base_url);
+    info(&format!("Launching {}", launch_mode.description));
+    // Launch wrappers come first, then wine on Linux and macOS ( we have to launch the game through wine ), then the client
+    let mut command_line : Vec<std::ffi::OsString> = config.launch_wrapper.iter().map(std::ffi::OsString::from).collect();
+    #[cfg(not(target_os = "windows"))]
+    command_line.push(config.wine().into());
+    command_line.push(client_executable_path.clone().into_os_string());
+    let mut command = std::process::Command::new(&command_line[0]);
+    command.args(&command_line[1..]);
+    let ticket_handoff = match launch::add_client_arguments(&mut command, &launch_request, &authentication_url, ticket_passing, client_executable_path.parent().unwrap()) {
+        Ok(ticket_handoff) => ticket_handoff,
+        Err(e) => {
+            error(&format!("Failed to hand the authentication ticket to the client: {}", e));
             std::thread::sleep(std::time::Duration::from_secs(10));
             std::process::exit(0);
         }
+    };
+    if let Some(client_variant) = client_variant {
+        command.args(&client_variant.arguments);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        command.spawn().unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(5));
+        ticket_handoff.finish();
+        std::process::exit(0);
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        // We must wait for the game to exit before exiting the bootstrapper
+        let mut child = command.spawn().unwrap();
+        ticket_handoff.finish();
+        child.wait().unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        std::process::exit(0);
     }
 }
@@ -0,0 +1,196 @@
+use colored::*;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+use crate::clients::ClientVariant;
+use crate::debug;
+use crate::download::{http_get, DownloadError};
+use crate::extract::ArchiveFormat;
+use crate::mirrors::Mirrors;
+use crate::retry::RetryPolicy;
+
+// The manifest is served by the setup server ( and its mirrors ) at https://{setup_url}/{version}-manifest.json
+// and lists every package that makes up a client version, e.g.
+// {
+//     "version": "version-abcdef",
+//     "packages": [
+//         { "name": "SyntaxApp.zip", "size": 1234, "sha256": "...", "destination": "" },
+//         { "name": "content-fonts.zip", "size": 5678, "sha256": "...", "destination": "content/fonts" },
+//         { "name": "content-music.zip", "size": 9012, "sha256": "...", "destination": "content/music", "pack": "music" },
+//         { "name": "content-sky", "size": 3456, "sha256": "...", "destination": "content/sky", "format": "tar.zst" }
+//     ]
+// }
+// A copy of the manifest is kept in every installed version directory so the next update can tell what changed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Manifest {
+    pub version: String,
+    pub packages: Vec<Package>,
+    // Client years added or changed by the server, see ClientVariant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clients: Vec<ClientVariant>,
+    // Digests of the files that are downloaded as they are instead of installed as packages, e.g.
+    // "files": [ { "name": "SyntaxPlayerLinuxLauncher", "size": 1234, "sha256": "..." } ]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<PublishedFile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    // Relative to the version directory, empty means the version directory itself
+    #[serde(default)]
+    pub destination: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<Patch>,
+    // Optional packages name the content pack they belong to ( music, sky, ... ), everything else is required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pack: Option<String>,
+    // zip, tar.zst or tar.xz, taken from the extension of the name when the manifest does not say
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ArchiveFormat>,
+}
+
+// The bootstrapper of each platform and the macOS app bundle, these can come from another host than the packages
+// but are only run once they match the digest the setup server published for them
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PublishedFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// A binary patch that turns an older build of a package ( identified by its sha256 ) into this one, made with
+// zstd --patch-from=<old package> <new package> -o <patch>
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Patch {
+    pub from: String,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Patch {
+    pub fn path( &self, version: &str ) -> String {
+        format!("{}-{}", version, self.name)
+    }
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Fetch(DownloadError),
+    Parse(serde_json::Error),
+    VersionMismatch { expected: String, found: String },
+    InvalidDestination { package: String, destination: String },
+    InvalidDigest { package: String, digest: String },
+    InvalidExecutable { year: String, executable: String },
+}
+
+impl std::fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManifestError::Fetch(e) => write!(f, "failed to fetch manifest: {}", e),
+            ManifestError::Parse(e) => write!(f, "failed to parse manifest: {}", e),
+            ManifestError::VersionMismatch { expected, found } => write!(f, "manifest is for version {} but expected {}", found, expected),
+            ManifestError::InvalidDestination { package, destination } => write!(f, "package {} has an invalid destination \"{}\"", package, destination),
+            ManifestError::InvalidDigest { package, digest } => write!(f, "package {} has an invalid sha256 \"{}\"", package, digest),
+            ManifestError::InvalidExecutable { year, executable } => write!(f, "client {} has an invalid executable \"{}\"", year, executable),
+        }
+    }
+}
+
+impl Package {
+    // Where the package lives relative to a mirror
+    pub fn path( &self, version: &str ) -> String {
+        format!("{}-{}", version, self.name)
+    }
+
+    pub fn target_directory( &self, version_directory: &Path ) -> PathBuf {
+        version_directory.join(&self.destination)
+    }
+
+    pub fn format( &self ) -> ArchiveFormat {
+        self.format.unwrap_or_else(|| ArchiveFormat::from_name(&self.name))
+    }
+}
+
+impl Manifest {
+    pub fn parse( body: &str, version: &str ) -> Result<Manifest, ManifestError> {
+        let manifest: Manifest = serde_json::from_str(body).map_err(ManifestError::Parse)?;
+        if manifest.version != version {
+            return Err(ManifestError::VersionMismatch { expected: version.to_string(), found: manifest.version });
+        }
+        // Destinations come from the server, never let them point outside of the version directory
+        for package in &manifest.packages {
+            if !is_inside_version_directory(&package.destination) {
+                return Err(ManifestError::InvalidDestination { package: package.name.clone(), destination: package.destination.clone() });
+            }
+        }
+        // Digests name files in the package cache, anything but a sha256 could point it at another file
+        for package in &manifest.packages {
+            let mut digests = std::iter::once(&package.sha256).chain(package.patches.iter().flat_map(|patch| [&patch.from, &patch.sha256]));
+            if let Some(digest) = digests.find(|digest| !is_sha256(digest)) {
+                return Err(ManifestError::InvalidDigest { package: package.name.clone(), digest: digest.clone() });
+            }
+        }
+        if let Some(file) = manifest.files.iter().find(|file| !is_sha256(&file.sha256)) {
+            return Err(ManifestError::InvalidDigest { package: file.name.clone(), digest: file.sha256.clone() });
+        }
+        // Clients get a directory of their own, it is replaced whenever the client is installed
+        for client in &manifest.clients {
+            if !is_inside_version_directory(&client.directory) || is_version_directory(&client.directory) {
+                return Err(ManifestError::InvalidDestination { package: client.package.clone(), destination: client.directory.clone() });
+            }
+            // The executable is joined onto the client directory and run, it has to be a file inside of it
+            if let Some(executable) = client.executable.as_ref().filter(|executable| !is_inside_version_directory(executable) || is_version_directory(executable)) {
+                return Err(ManifestError::InvalidExecutable { year: client.year.clone(), executable: executable.clone() });
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn load( path: &Path ) -> Option<Manifest> {
+        let body = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&body).ok()
+    }
+
+    pub fn save( &self, path: &Path ) -> Result<(), std::io::Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    // The same version with only the packages `keep` returns true for
+    pub fn subset<F: Fn(&Package) -> bool>( &self, keep: F ) -> Manifest {
+        Manifest { version: self.version.clone(), packages: self.packages.iter().filter(|package| keep(package)).cloned().collect(), clients: self.clients.clone(), files: self.files.clone() }
+    }
+
+    pub fn published_file( &self, name: &str ) -> Option<&PublishedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn total_size( &self ) -> u64 {
+        self.packages.iter().map(|package| package.size).sum()
+    }
+}
+
+fn is_sha256( digest: &str ) -> bool {
+    digest.len() == 64 && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn is_inside_version_directory( path: &str ) -> bool {
+    Path::new(path).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+// "", "." and "./." all name the version directory itself
+fn is_version_directory( path: &str ) -> bool {
+    Path::new(path).components().all(|component| matches!(component, Component::CurDir))
+}
+
+pub async fn fetch_manifest( client: &Client, mirrors: &Mirrors, version: &str, retry_policy: &RetryPolicy ) -> Result<Manifest, ManifestError> {
+    let manifest_path = format!("{}-manifest.json", version);
+    let body = mirrors.run(&manifest_path, None, |url| async move { http_get(client, &url, retry_policy).await }).await.map_err(ManifestError::Fetch)?;
+    let manifest = Manifest::parse(&body, version)?;
+    debug(&format!("Manifest lists {} packages ({} bytes)", manifest.packages.len().to_string().bright_blue(), manifest.total_size().to_string().bright_blue()));
+    Ok(manifest)
+}
@@ -0,0 +1,97 @@
+use colored::*;
+use indicatif::MultiProgress;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::download::DownloadError;
+use crate::{debug, error};
+
+pub const DEFAULT_MIRRORS: [&str; 2] = ["setup.syntax.eco", "d2f3pa9j0u8v6f.cloudfront.net"];
+
+// A mirror that fails this many requests in a row is skipped for the rest of the run
+const UNHEALTHY_AFTER_FAILURES: u32 = 2;
+
+struct Mirror {
+    base_url: String,
+    consecutive_failures: AtomicU32,
+    unhealthy: AtomicBool,
+}
+
+pub struct Mirrors {
+    mirrors: Vec<Mirror>,
+}
+
+impl Mirrors {
+    // Mirrors are either a bare host ( setup.syntax.eco ) or a full base url ( https://example.com/syntax )
+    pub fn new( hosts: &[String] ) -> Mirrors {
+        let mirrors = hosts.iter().map(|host| {
+            let host = host.trim().trim_end_matches('/');
+            let base_url = if host.contains("://") { host.to_string() } else { format!("https://{}", host) };
+            Mirror { base_url, consecutive_failures: AtomicU32::new(0), unhealthy: AtomicBool::new(false) }
+        }).collect();
+        Mirrors { mirrors }
+    }
+
+    pub fn base_urls( &self ) -> Vec<&str> {
+        self.mirrors.iter().map(|mirror| mirror.base_url.as_str()).collect()
+    }
+
+    // Healthy mirrors in the configured order, followed by the unhealthy ones as a last resort
+    fn candidates( &self ) -> Vec<&Mirror> {
+        let (healthy, unhealthy): (Vec<&Mirror>, Vec<&Mirror>) = self.mirrors.iter().partition(|mirror| !mirror.unhealthy.load(Ordering::Relaxed));
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    fn record_success( &self, mirror: &Mirror ) {
+        mirror.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    fn record_failure( &self, mirror: &Mirror, progress: Option<&MultiProgress> ) {
+        let failures = mirror.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= UNHEALTHY_AFTER_FAILURES && !mirror.unhealthy.swap(true, Ordering::Relaxed) {
+            let message = format!("Mirror {} keeps failing, skipping it for the rest of this run", mirror.base_url.bright_blue());
+            match progress {
+                Some(progress) => progress.suspend(|| error(&message)),
+                None => error(&message),
+            }
+        }
+    }
+
+    // Runs the operation against each mirror in turn until one of them succeeds. The operation gets the full url
+    // for `path` on that mirror and is expected to do its own retrying
+    pub async fn run<T, F, Fut>( &self, path: &str, progress: Option<&MultiProgress>, mut operation: F ) -> Result<T, DownloadError>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T, DownloadError>>,
+    {
+        let candidates = self.candidates();
+        let mut last_error = None;
+        for (index, mirror) in candidates.iter().enumerate() {
+            let url = format!("{}/{}", mirror.base_url, path);
+            match operation(url).await {
+                Ok(result) => {
+                    self.record_success(mirror);
+                    return Ok(result);
+                },
+                Err(e) => {
+                    // Nothing another mirror can do about a full disk
+                    if e.is_local() {
+                        return Err(e);
+                    }
+                    self.record_failure(mirror, progress);
+                    let message = match candidates.get(index + 1) {
+                        Some(next_mirror) => format!("Failed to fetch {} from {}: {}, trying {}", path.bright_blue(), mirror.base_url.bright_blue(), e.to_string().bright_red(), next_mirror.base_url.bright_blue()),
+                        None => format!("Failed to fetch {} from {}: {}, no mirrors left", path.bright_blue(), mirror.base_url.bright_blue(), e.to_string().bright_red()),
+                    };
+                    match progress {
+                        Some(progress) => progress.suspend(|| error(&message)),
+                        None => error(&message),
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        debug(&format!("All mirrors failed for {}", path.bright_blue()));
+        Err(last_error.expect("no mirrors configured"))
+    }
+}
@@ -0,0 +1,102 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::fetch::PackageFetcher;
+use crate::install::{self, InstallError};
+use crate::manifest::{Manifest, Package};
+use crate::state::InstallState;
+use crate::{debug, info};
+
+// Installs without a state file keep their PackRecord here instead
+const PACK_RECORD_FILENAME: &str = "packs.json";
+
+// Which optional packs an install has, part of its InstallState
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PackRecord {
+    pub present: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+impl PackRecord {
+    pub fn load( version_directory: &Path ) -> Option<PackRecord> {
+        if let Some(state) = InstallState::load(version_directory) {
+            return Some(state.packs);
+        }
+        let body = std::fs::read_to_string(version_directory.join(PACK_RECORD_FILENAME)).ok()?;
+        serde_json::from_str(&body).ok()
+    }
+
+    fn save_without_state( &self, version_directory: &Path ) -> Result<(), std::io::Error> {
+        std::fs::write(version_directory.join(PACK_RECORD_FILENAME), serde_json::to_string_pretty(self)?)
+    }
+
+    // What an install of `manifest` without the `excluded` packs ends up with
+    pub fn for_manifest( manifest: &Manifest, excluded: &[String] ) -> PackRecord {
+        let mut present = manifest.packages.iter()
+            .filter_map(|package| package.pack.clone())
+            .filter(|pack| !is_excluded(pack, excluded))
+            .collect::<Vec<String>>();
+        present.sort();
+        present.dedup();
+        PackRecord { present, excluded: excluded.to_vec() }
+    }
+}
+
+// "music, Sky" -> ["music", "sky"]
+pub fn parse_pack_list( value: &str ) -> Vec<String> {
+    value.split(',').map(|pack| pack.trim().to_ascii_lowercase()).filter(|pack| !pack.is_empty()).collect()
+}
+
+fn is_excluded( pack: &str, excluded: &[String] ) -> bool {
+    excluded.iter().any(|excluded_pack| excluded_pack.eq_ignore_ascii_case(pack))
+}
+
+pub fn is_selected( package: &Package, excluded: &[String] ) -> bool {
+    package.pack.as_ref().map(|pack| !is_excluded(pack, excluded)).unwrap_or(true)
+}
+
+// Brings an existing install in line with a new selection, installing packs that are now wanted and removing the
+// ones that are not. A pack is only removed when it has directories of its own, files it shares with other
+// packages stay until the next update
+pub async fn change_selection( manifest: &Manifest, record: &PackRecord, excluded: &[String], fetcher: &PackageFetcher<'_>, version_directory: &Path ) -> Result<(), InstallError> {
+    // Installs from before the state file are left without one, a state missing most files would hide them from repairs
+    let mut state = InstallState::load(version_directory);
+    let added = manifest.subset(|package| package.pack.as_ref().map(|pack| !is_excluded(pack, excluded) && !record.present.contains(pack)).unwrap_or(false));
+    if !added.packages.is_empty() {
+        info(&format!("Installing {} optional packages", added.packages.len().to_string().bright_blue()));
+        let package_files = install::fetch_and_extract(fetcher, &added, version_directory).await?;
+        if let Some(state) = &mut state {
+            for (package, files) in added.packages.iter().zip(package_files) {
+                state.record(package, files, &package.target_directory(version_directory), version_directory);
+            }
+        }
+    }
+
+    for package in &manifest.packages {
+        let removed = package.pack.as_ref().map(|pack| is_excluded(pack, excluded) && record.present.contains(pack)).unwrap_or(false);
+        if !removed {
+            continue;
+        }
+        let shared = package.destination.is_empty() || manifest.packages.iter().any(|other| other.name != package.name && other.destination == package.destination);
+        let target_directory = package.target_directory(version_directory);
+        if shared {
+            debug(&format!("Leaving {} in place, its directory is shared with other packages", package.name.bright_blue()));
+        } else if target_directory.exists() {
+            info(&format!("Removing {}", package.name.bright_blue()));
+            std::fs::remove_dir_all(&target_directory)?;
+            if let Some(state) = &mut state {
+                state.remove(&package.name);
+            }
+        }
+    }
+    let record = PackRecord::for_manifest(manifest, excluded);
+    match state {
+        Some(mut state) => {
+            state.packs = record;
+            state.save(version_directory)?;
+        },
+        None => record.save_without_state(version_directory)?,
+    }
+    Ok(())
+}
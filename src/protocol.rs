@@ -0,0 +1,139 @@
+use std::path::Path;
+
+#[cfg(target_os = "windows")]
+use winreg::enums::*;
+#[cfg(target_os = "windows")]
+use winreg::RegKey;
+
+use crate::info;
+
+#[cfg(target_os = "macos")]
+const APP_BUNDLE_PATH: &str = "/Applications/Syntax Player.app";
+#[cfg(target_os = "macos")]
+const LSREGISTER_PATH: &str = "/System/Library/Frameworks/CoreServices.framework/Frameworks/LaunchServices.framework/Support/lsregister";
+
+// Points the syntax-player scheme at `bootstrapper_path`
+pub fn register( bootstrapper_path: &Path ) {
+    info("Installing syntax-player scheme");
+    #[cfg(target_os = "windows")]
+    {
+        let hkey_current_user = RegKey::predef(HKEY_CURRENT_USER);
+        let hkey_classes_root : RegKey = hkey_current_user.open_subkey("Software\\Classes").unwrap();
+        let hkey_syntax_player = hkey_classes_root.create_subkey("syntax-player").unwrap().0;
+        let hkey_syntax_player_shell = hkey_syntax_player.create_subkey("shell").unwrap().0;
+        let hkey_syntax_player_shell_open = hkey_syntax_player_shell.create_subkey("open").unwrap().0;
+        let hkey_syntax_player_shell_open_command = hkey_syntax_player_shell_open.create_subkey("command").unwrap().0;
+        let defaulticon = hkey_syntax_player.create_subkey("DefaultIcon").unwrap().0;
+        hkey_syntax_player_shell_open_command.set_value("", &format!("\"{}\" \"%1\"", bootstrapper_path.to_str().unwrap())).unwrap();
+        defaulticon.set_value("", &format!("\"{}\",0", bootstrapper_path.to_str().unwrap())).unwrap();
+        hkey_syntax_player.set_value("", &"URL: Syntax Protocol").unwrap();
+        hkey_syntax_player.set_value("URL Protocol", &"").unwrap();
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // Linux support
+        // We have to write a .desktop file to ~/.local/share/applications
+        let desktop_file_path = dirs::data_local_dir().unwrap().join("applications").join("syntax-player.desktop");
+        let desktop_file = format!(
+"[Desktop Entry]
+Name=Syntax Launcher
+Exec={} %u
+Terminal=true
+Type=Application
+MimeType=x-scheme-handler/syntax-player;
+Icon={}
+StartupWMClass=SyntaxLauncher
+Categories=Game;
+Comment=Syntax Launcher
+", bootstrapper_path.to_str().unwrap(), bootstrapper_path.to_str().unwrap());
+        std::fs::write(desktop_file_path, desktop_file).unwrap();
+        // We also have to write a mimeapps.list file to ~/.config
+        let mimeapps_list_path = dirs::config_dir().unwrap().join("mimeapps.list");
+        let mimeapps_list =
+"[Default Applications]
+x-scheme-handler/syntax-player=syntax-player.desktop
+";
+        std::fs::write(mimeapps_list_path, mimeapps_list).unwrap();
+        // We also have to write a mimeapps.list file to ~/.local/share
+        let mimeapps_list_path = dirs::data_local_dir().unwrap().join("mimeapps.list");
+        std::fs::write(mimeapps_list_path, mimeapps_list).unwrap();
+    }
+    #[cfg(target_os = "macos")]
+    {
+        // Syntax Player.app runs the script, which opens the bootstrapper in a terminal. The path is quoted for the
+        // shell and then escaped for the AppleScript string the shell command ends up in
+        let script = format!(
+r#"import shlex, subprocess, sys
+command = "unset HISTFILE && " + " ".join(shlex.quote(argument) for argument in [{:?}] + sys.argv[1:2]) + " && exit"
+command = command.replace("\\", "\\\\").replace("\"", "\\\"")
+subprocess.run(["osascript", "-e", "tell application \"Terminal\"", "-e", "do script \"" + command + "\"", "-e", "activate application \"Terminal\"", "-e", "end tell"])
+"#, bootstrapper_path.to_str().unwrap()
+        );
+        let script_path = Path::new(APP_BUNDLE_PATH).join("Contents").join("Resources").join("script");
+        std::fs::write(&script_path, script).unwrap();
+        std::process::Command::new("chmod").arg("+x").arg(&script_path).status().unwrap();
+        std::process::Command::new("duti").arg("-s").arg("Syn.tax.Player").arg("syntax-player").status().unwrap();
+    }
+}
+
+// Removes everything `register` set up, returns what was removed
+pub fn unregister() -> Result<Vec<String>, std::io::Error> {
+    let mut removed = Vec::new();
+    #[cfg(target_os = "windows")]
+    {
+        let hkey_current_user = RegKey::predef(HKEY_CURRENT_USER);
+        let hkey_classes_root : RegKey = hkey_current_user.open_subkey_with_flags("Software\\Classes", KEY_ALL_ACCESS)?;
+        match hkey_classes_root.delete_subkey_all("syntax-player") {
+            Ok(()) => removed.push("HKEY_CURRENT_USER\\Software\\Classes\\syntax-player".to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let desktop_file_path = dirs::data_local_dir().unwrap().join("applications").join("syntax-player.desktop");
+        if desktop_file_path.exists() {
+            std::fs::remove_file(&desktop_file_path)?;
+            removed.push(desktop_file_path.to_str().unwrap().to_string());
+        }
+        for mimeapps_list_path in [dirs::config_dir().unwrap().join("mimeapps.list"), dirs::data_local_dir().unwrap().join("mimeapps.list")] {
+            if remove_mimeapps_entry(&mimeapps_list_path)? {
+                removed.push(format!("x-scheme-handler/syntax-player from {}", mimeapps_list_path.to_str().unwrap()));
+            }
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let app_bundle_path = Path::new(APP_BUNDLE_PATH);
+        if app_bundle_path.exists() {
+            // Launch Services forgets the schemes the app claimed, the handler duti set can not be taken back and
+            // points at nothing once the app is gone
+            let _ = std::process::Command::new(LSREGISTER_PATH).arg("-u").arg(app_bundle_path).status();
+            std::fs::remove_dir_all(app_bundle_path)?;
+            removed.push(APP_BUNDLE_PATH.to_string());
+            info("Left the syntax-player handler set with duti in the Launch Services preferences, it does nothing without Syntax Player.app");
+        }
+    }
+    Ok(removed)
+}
+
+// Takes our scheme handler out of a mimeapps.list, the file goes too when nothing else is left in it
+#[cfg(target_os = "linux")]
+fn remove_mimeapps_entry( mimeapps_list_path: &Path ) -> Result<bool, std::io::Error> {
+    let mimeapps_list = match std::fs::read_to_string(mimeapps_list_path) {
+        Ok(mimeapps_list) => mimeapps_list,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let lines = mimeapps_list.lines().collect::<Vec<&str>>();
+    let remaining = lines.iter().copied().filter(|line| !line.trim_start().starts_with("x-scheme-handler/syntax-player=")).collect::<Vec<&str>>();
+    if remaining.len() == lines.len() {
+        return Ok(false);
+    }
+    if remaining.iter().all(|line| line.trim().is_empty() || line.trim_start().starts_with('[')) {
+        std::fs::remove_file(mimeapps_list_path)?;
+    } else {
+        std::fs::write(mimeapps_list_path, remaining.join("\n") + "\n")?;
+    }
+    Ok(true)
+}
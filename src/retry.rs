@@ -0,0 +1,89 @@
+use colored::*;
+use indicatif::MultiProgress;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::future::Future;
+use std::time::Duration;
+
+use crate::download::DownloadError;
+use crate::{error, info};
+
+// A server asking us to wait longer than this is treated as if it asked for this long
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Exponential backoff with jitter, the first retry waits somewhere between base_delay / 2 and base_delay.
+    // A Retry-After from the server replaces the backoff entirely
+    pub fn delay( &self, attempt: u32, retry_after: Option<Duration> ) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return std::cmp::min(retry_after, MAX_RETRY_AFTER);
+        }
+        let exponent = attempt.saturating_sub(1).min(16);
+        let backoff = std::cmp::min(self.base_delay.saturating_mul(1 << exponent), self.max_delay);
+        let half = backoff / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+pub fn parse_retry_after( headers: &HeaderMap ) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.signed_duration_since(chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO);
+    Some(wait)
+}
+
+// Runs the operation until it succeeds, fails with an error that is not worth retrying or runs out of attempts
+pub async fn with_retry<T, F, Fut>( policy: &RetryPolicy, description: &str, progress: Option<&MultiProgress>, mut operation: F ) -> Result<T, DownloadError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DownloadError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Ok(result) => {
+                if attempt > 1 {
+                    let message = format!("{} succeeded after {} attempts", description.bright_blue(), attempt);
+                    match progress {
+                        Some(progress) => progress.suspend(|| info(&message)),
+                        None => info(&message),
+                    }
+                }
+                return Ok(result);
+            },
+            Err(e) => {
+                if !e.is_retryable() || attempt >= policy.max_attempts {
+                    return Err(e);
+                }
+                let delay = policy.delay(attempt, e.retry_after());
+                let message = format!("{} failed: {}, retrying in {:.1}s (attempt {}/{})", description.bright_blue(), e.to_string().bright_red(), delay.as_secs_f64(), attempt + 1, policy.max_attempts);
+                match progress {
+                    Some(progress) => progress.suspend(|| error(&message)),
+                    None => error(&message),
+                }
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
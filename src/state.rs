@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::inventory::{self, InstalledFile};
+use crate::manifest::{Manifest, Package};
+use crate::packs::PackRecord;
+
+// Kept in every installed version directory. It is written last when an install finishes and updated whenever
+// packages are added or removed, so a version directory with a state file is a complete install
+pub const STATE_FILENAME: &str = "state.json";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstallState {
+    pub version: String,
+    // Seconds since the unix epoch
+    pub installed_at: u64,
+    pub bootstrapper_version: String,
+    pub packages: Vec<InstalledPackage>,
+    pub packs: PackRecord,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub sha256: String,
+    pub size: u64,
+    // Where the package was extracted, relative to the version directory
+    pub directory: String,
+    pub files: Vec<InstalledFile>,
+}
+
+impl InstalledPackage {
+    // Relative to the version directory
+    pub fn full_path( &self, file: &InstalledFile ) -> String {
+        join_path(&self.directory, &file.path)
+    }
+}
+
+fn join_path( directory: &str, path: &str ) -> String {
+    if directory.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", directory, path)
+    }
+}
+
+// Installs from before the state file are recognized by their AppSettings.xml
+pub fn is_installed( version_directory: &Path ) -> bool {
+    version_directory.join(STATE_FILENAME).is_file() || version_directory.join("AppSettings.xml").is_file()
+}
+
+impl InstallState {
+    // The state of a fresh install of `packages` into `version_directory`
+    pub fn for_install( manifest: &Manifest, excluded_packs: &[String], packages: Vec<(&Package, Vec<InstalledFile>)>, version_directory: &Path ) -> InstallState {
+        let installed_at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut state = InstallState {
+            version: manifest.version.clone(),
+            installed_at,
+            bootstrapper_version: env!("CARGO_PKG_VERSION").to_string(),
+            packages: Vec::new(),
+            packs: PackRecord::for_manifest(manifest, excluded_packs),
+        };
+        for (package, files) in packages {
+            state.record(package, files, &package.target_directory(version_directory), version_directory);
+        }
+        state
+    }
+
+    pub fn load( version_directory: &Path ) -> Option<InstallState> {
+        let body = std::fs::read_to_string(version_directory.join(STATE_FILENAME)).ok()?;
+        serde_json::from_str(&body).ok()
+    }
+
+    pub fn save( &self, version_directory: &Path ) -> Result<(), std::io::Error> {
+        std::fs::write(version_directory.join(STATE_FILENAME), serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn installed_time( &self ) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.installed_at)
+    }
+
+    // Records a package extracted to `target_directory` with the files inventory::package_files found in it. Files it
+    // overwrote belong to it from now on
+    pub fn record( &mut self, package: &Package, files: Vec<InstalledFile>, target_directory: &Path, version_directory: &Path ) {
+        let directory = inventory::inventory_path(target_directory.strip_prefix(version_directory).expect("packages are extracted inside the version directory"));
+        let installed_package = InstalledPackage {
+            name: package.name.clone(),
+            sha256: package.sha256.to_ascii_lowercase(),
+            size: package.size,
+            directory,
+            files,
+        };
+        let overwritten = installed_package.files.iter().map(|file| installed_package.full_path(file)).collect::<HashSet<String>>();
+        self.remove(&package.name);
+        for other in &mut self.packages {
+            let directory = &other.directory;
+            other.files.retain(|file| !overwritten.contains(&join_path(directory, &file.path)));
+        }
+        self.packages.push(installed_package);
+    }
+
+    pub fn remove( &mut self, package_name: &str ) {
+        self.packages.retain(|package| package.name != package_name);
+    }
+
+    // Whether an installed package put `path` ( relative to the version directory ) there
+    pub fn records( &self, path: &str ) -> bool {
+        self.packages.iter().any(|package| package.files.iter().any(|file| package.full_path(file) == path))
+    }
+
+    pub fn file_count( &self ) -> usize {
+        self.packages.iter().map(|package| package.files.len()).sum()
+    }
+
+    // Uncompressed size of every installed file
+    pub fn installed_size( &self ) -> u64 {
+        self.packages.iter().flat_map(|package| package.files.iter()).map(|file| file.size).sum()
+    }
+}
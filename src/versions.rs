@@ -0,0 +1,149 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::delta::INSTALLED_MANIFEST_FILENAME;
+use crate::install::is_transient_directory;
+use crate::state::{self, InstallState};
+use crate::{debug, info};
+
+// Number of installed versions kept around for rolling back, including the current one
+pub const DEFAULT_KEEP_VERSIONS: usize = 3;
+
+// Written by a rollback, lives in the installation directory
+const ROLLBACK_FILENAME: &str = "rollback.json";
+
+pub struct InstalledVersion {
+    pub version: String,
+    pub path: PathBuf,
+    pub installed_at: SystemTime,
+    // None for installs from before the state file
+    pub state: Option<InstallState>,
+}
+
+// Every complete install in the versions directory, newest first. Installs from before the state file use the
+// modification time of their manifest as the install time, or of the directory when they have no manifest either
+pub fn installed_versions( versions_directory: &Path ) -> Vec<InstalledVersion> {
+    let mut versions = Vec::new();
+    let entries = match std::fs::read_dir(versions_directory) {
+        Ok(entries) => entries,
+        Err(_) => return versions,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || is_transient_directory(&path) || !state::is_installed(&path) {
+            continue;
+        }
+        let state = InstallState::load(&path);
+        let installed_at = match &state {
+            Some(state) => state.installed_time(),
+            None => std::fs::metadata(path.join(INSTALLED_MANIFEST_FILENAME))
+                .or_else(|_| std::fs::metadata(&path))
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        };
+        versions.push(InstalledVersion { version: entry.file_name().to_string_lossy().to_string(), path, installed_at, state });
+    }
+    versions.sort_by_key(|installed| std::cmp::Reverse(installed.installed_at));
+    versions
+}
+
+// Removes leftovers of unfinished installs and all but the `keep` most recent versions. The current version always stays
+pub fn apply_retention( versions_directory: &Path, current_version: &str, keep: usize ) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(versions_directory)? {
+        let path = entry?.path();
+        if path.is_dir() && (is_transient_directory(&path) || !state::is_installed(&path)) && path.file_name().map(|name| name != current_version).unwrap_or(true) {
+            debug(&format!("Removing incomplete install {}", path.to_str().unwrap().bright_blue()));
+            std::fs::remove_dir_all(&path)?;
+        }
+    }
+    let older_versions = installed_versions(versions_directory).into_iter().filter(|installed| installed.version != current_version);
+    for installed in older_versions.skip(keep.saturating_sub(1)) {
+        info(&format!("Removing old version {}", installed.version.bright_blue()));
+        std::fs::remove_dir_all(&installed.path)?;
+    }
+    Ok(())
+}
+
+// A rollback keeps launches on `version` for as long as the setup server still hands out `skipped`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Rollback {
+    pub version: String,
+    pub skipped: String,
+}
+
+impl Rollback {
+    pub fn load( installation_directory: &Path ) -> Option<Rollback> {
+        let body = std::fs::read_to_string(installation_directory.join(ROLLBACK_FILENAME)).ok()?;
+        serde_json::from_str(&body).ok()
+    }
+
+    pub fn save( &self, installation_directory: &Path ) -> Result<(), std::io::Error> {
+        let body = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(installation_directory.join(ROLLBACK_FILENAME), body)
+    }
+
+    pub fn clear( installation_directory: &Path ) {
+        let _ = std::fs::remove_file(installation_directory.join(ROLLBACK_FILENAME));
+    }
+}
+
+// The version launches should use given what the setup server reports as latest, following a rollback while it applies
+pub fn resolve_current_version( installation_directory: &Path, versions_directory: &Path, latest_version: &str ) -> String {
+    let rollback = match Rollback::load(installation_directory) {
+        Some(rollback) => rollback,
+        None => return latest_version.to_string(),
+    };
+    if rollback.skipped != latest_version {
+        info(&format!("Version {} is out, leaving the rollback to {}", latest_version.bright_blue(), rollback.version.bright_blue()));
+        Rollback::clear(installation_directory);
+        return latest_version.to_string();
+    }
+    // Also catches rollbacks to versions that can not stay rolled back, see rollback
+    if InstallState::load(&versions_directory.join(&rollback.version)).is_none() {
+        info(&format!("Can not stay on rolled back version {}, using {}", rollback.version.bright_blue(), latest_version.bright_blue()));
+        Rollback::clear(installation_directory);
+        return latest_version.to_string();
+    }
+    info(&format!("Rolled back to {}, skipping {}", rollback.version.bright_blue(), latest_version.bright_blue()));
+    rollback.version
+}
+
+// Makes `target` ( or the version installed before the current one ) current again
+pub fn rollback( installation_directory: &Path, versions_directory: &Path, target: Option<&str>, bootstrapper_filename: &str ) -> Result<InstalledVersion, String> {
+    let installed = installed_versions(versions_directory);
+    let current_version = Rollback::load(installation_directory).map(|rollback| rollback.version)
+        .or_else(|| installed.first().map(|newest| newest.version.clone()))
+        .ok_or("no versions are installed")?;
+    let skipped = Rollback::load(installation_directory).map(|rollback| rollback.skipped)
+        .unwrap_or_else(|| current_version.clone());
+
+    let target_version = match target {
+        Some(target) => installed.into_iter().find(|installed| installed.version == target)
+            .ok_or(format!("version {} is not installed", target))?,
+        None => {
+            let current_installed_at = installed.iter().find(|installed| installed.version == current_version).map(|installed| installed.installed_at);
+            installed.into_iter().find(|installed| installed.version != current_version && current_installed_at.map(|at| installed.installed_at < at).unwrap_or(true))
+                .ok_or("there is no older version to roll back to")?
+        }
+    };
+    if target_version.version == current_version {
+        return Err(format!("{} is already the current version", current_version));
+    }
+    if !target_version.path.join(bootstrapper_filename).is_file() {
+        return Err(format!("version {} has no bootstrapper", target_version.version));
+    }
+    // Bootstrappers from before the state file know nothing of rollbacks, they would relaunch the latest version,
+    // which resolves back to the rolled back one and relaunches it again
+    if target_version.state.is_none() {
+        return Err(format!("version {} was installed by a bootstrapper that can not be rolled back to", target_version.version));
+    }
+
+    if target_version.version == skipped {
+        Rollback::clear(installation_directory);
+    } else {
+        Rollback { version: target_version.version.clone(), skipped }.save(installation_directory).map_err(|e| e.to_string())?;
+    }
+    Ok(target_version)
+}